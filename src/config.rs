@@ -0,0 +1,152 @@
+use std::env;
+use std::fs;
+use std::fs::DirBuilder;
+use std::fs::File;
+use std::io::Read;
+use std::io;
+use std::path::PathBuf;
+
+use toml;
+
+pub const KEYS: [&str; 11] = ["idle_hours", "round_minutes", "timezone", "week_start", "weekend", "workday_end",
+	"default_project", "daily_goal_hours", "rate", "currency", "split_at_midnight"];
+
+pub struct Config {
+	pub idle_hours: f64,
+	pub round_minutes: Option<i64>,
+	pub timezone: String,
+	pub week_start: String,
+	pub weekend: Vec<String>,
+	pub workday_end: String,
+	pub default_project: Option<String>,
+	pub daily_goal_hours: Option<f64>,
+	pub rate: Option<f64>,
+	pub currency: String,
+	pub split_at_midnight: bool
+}
+
+impl Default for Config {
+	fn default() -> Config {
+		Config {
+			idle_hours: 12.0,
+			round_minutes: None,
+			timezone: String::from("local"),
+			week_start: String::from("mon"),
+			weekend: vec![String::from("sat"), String::from("sun")],
+			workday_end: String::from("17:00"),
+			default_project: None,
+			daily_goal_hours: None,
+			rate: None,
+			currency: String::from("USD"),
+			split_at_midnight: false
+		}
+	}
+}
+
+// PUNCH_CONFIG points at another config file, e.g. to try settings out.
+pub fn get_config_path() -> PathBuf {
+	if let Some(path) = env::var_os("PUNCH_CONFIG") {
+		return PathBuf::from(path)
+	}
+	let mut config_file = PathBuf::new();
+    config_file.push(env::home_dir().unwrap());
+    config_file.push(".punch");
+    config_file.push("config.toml");
+    config_file
+}
+
+// The config file is optional; missing keys fall back to the built-in defaults and unknown
+// keys are ignored, so that older builds can read configs written for newer ones.
+pub fn read_config() -> Result<Config, String> {
+	let mut config = Config::default();
+	apply_table(&mut config, &read_config_table()?)?;
+	Ok(config)
+}
+
+// Rewrites the config file with `key` set, after checking that the result still loads.
+// Comments in the file aren't kept.
+pub fn set_config_value(key: &str, value: &str) -> Result<(), String> {
+	let parsed_value = match key {
+		"idle_hours" | "daily_goal_hours" | "rate" => toml::Value::Float(value.parse::<f64>().
+			map_err(|e| format!("{} must be a number: {}", key, e))?),
+		"round_minutes" => toml::Value::Integer(value.parse::<i64>().
+			map_err(|e| format!("{} must be an integer: {}", key, e))?),
+		"split_at_midnight" => toml::Value::Boolean(value.parse::<bool>().
+			map_err(|_| format!("{} must be true or false", key))?),
+		"weekend" => toml::Value::Array(value.split(',').map(|day| toml::Value::String(String::from(day.trim()))).
+			filter(|day| day.as_str() != Some("")).collect()),
+		_ if KEYS.contains(&key) => toml::Value::String(String::from(value)),
+		_ => return Err(format!("Unknown setting '{}', expected one of {}", key, KEYS.join(", ")))
+	};
+	let mut table = read_config_table()?;
+	table.insert(String::from(key), parsed_value);
+	apply_table(&mut Config::default(), &table)?;
+
+	let contents = toml::to_string(&toml::Value::Table(table)).map_err(|e| format!("Failed to format config: {}", e))?;
+	let config_path = get_config_path();
+	if let Some(parent) = config_path.parent() {
+		DirBuilder::new().recursive(true).create(parent).map_err(|e| format!("Failed to create config directory: {}", e))?;
+	}
+	fs::write(&config_path, contents).map_err(|e| format!("Failed to write config: {}", e))
+}
+
+fn read_config_table() -> Result<toml::value::Table, String> {
+	let mut contents = String::new();
+	match File::open(get_config_path()) {
+		Ok(mut f) => {
+			if let Err(e) = f.read_to_string(&mut contents) {
+				return Err(format!("Failed to read config: {}", e))
+			}
+		},
+		Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(toml::value::Table::new()),
+		Err(e) => return Err(format!("Failed to open config: {}", e))
+	}
+
+	match contents.parse::<toml::Value>() {
+		Ok(toml::Value::Table(table)) => Ok(table),
+		Ok(_) => Err(String::from("Config must be a table of settings")),
+		Err(e) => Err(format!("Failed to parse config: {}", e))
+	}
+}
+
+fn apply_table(config: &mut Config, table: &toml::value::Table) -> Result<(), String> {
+	if let Some(value) = table.get("idle_hours") {
+		config.idle_hours = value.as_float().or_else(|| value.as_integer().map(|hours| hours as f64)).
+			ok_or("idle_hours must be a number")?;
+	}
+	if let Some(value) = table.get("round_minutes") {
+		config.round_minutes = Some(value.as_integer().ok_or("round_minutes must be an integer")?);
+	}
+	if let Some(value) = table.get("timezone") {
+		config.timezone = String::from(value.as_str().ok_or("timezone must be a string")?);
+	}
+	if let Some(value) = table.get("week_start") {
+		config.week_start = String::from(value.as_str().ok_or("week_start must be a string")?);
+	}
+	if let Some(value) = table.get("weekend") {
+		let days = value.as_array().ok_or("weekend must be a list of days")?;
+		config.weekend = days.iter().map(|day| day.as_str().map(String::from).ok_or("weekend must be a list of days")).
+			collect::<Result<Vec<String>, &str>>()?;
+	}
+	if let Some(value) = table.get("workday_end") {
+		config.workday_end = String::from(value.as_str().ok_or("workday_end must be a string")?);
+	}
+	if let Some(value) = table.get("default_project") {
+		config.default_project = Some(String::from(value.as_str().ok_or("default_project must be a string")?));
+	}
+	if let Some(value) = table.get("daily_goal_hours") {
+		config.daily_goal_hours = Some(value.as_float().or_else(|| value.as_integer().map(|hours| hours as f64)).
+			ok_or("daily_goal_hours must be a number")?);
+	}
+	if let Some(value) = table.get("rate") {
+		config.rate = Some(value.as_float().or_else(|| value.as_integer().map(|rate| rate as f64)).
+			ok_or("rate must be a number")?);
+	}
+	if let Some(value) = table.get("currency") {
+		config.currency = String::from(value.as_str().ok_or("currency must be a string")?);
+	}
+	if let Some(value) = table.get("split_at_midnight") {
+		config.split_at_midnight = value.as_bool().ok_or("split_at_midnight must be true or false")?;
+	}
+	Ok(())
+}
@@ -0,0 +1,161 @@
+use std::io;
+use std::io::Write;
+
+use chrono::DateTime;
+use chrono::Local;
+use chrono::UTC;
+
+use journal::Journal;
+use session;
+
+pub type Exporter = fn(&Journal, &mut dyn Write) -> io::Result<()>;
+
+pub const FORMATS: [&str; 5] = ["csv", "csv-records", "csv-sessions", "jsonl", "ics"];
+
+pub fn exporter_for(format: &str) -> Option<Exporter> {
+	match format {
+		"csv" => Some(sessions_as_csv),
+		"csv-records" => Some(records_as_csv),
+		"csv-sessions" => Some(session_spans_as_csv),
+		"jsonl" => Some(records_as_jsonl),
+		"ics" => Some(sessions_as_ics),
+		_ => None
+	}
+}
+
+pub fn sessions_as_csv(journal: &Journal, out: &mut dyn Write) -> io::Result<()> {
+	writeln!(out, "date,punch_in,punch_out,duration_seconds")?;
+	for session in session::pair_sessions(&journal.all_records()?) {
+		let date = session.start.with_timezone(&Local).format("%F");
+		match session.end {
+			Some(end) => writeln!(out, "{},{},{},{}", date, format_iso_timestamp(session.start),
+				format_iso_timestamp(end), session.duration_until(end).num_seconds())?,
+			None => {
+				writeln!(out, "{},{},,", date, format_iso_timestamp(session.start))?;
+				eprintln!("Note: session started at {} is still open", session.start.with_timezone(&Local));
+			}
+		}
+	}
+	Ok(())
+}
+
+pub fn session_spans_as_csv(journal: &Journal, out: &mut dyn Write) -> io::Result<()> {
+	writeln!(out, "start,end,duration_seconds")?;
+	for session in session::pair_sessions(&journal.all_records()?) {
+		if let Some(end) = session.end {
+			writeln!(out, "{},{},{}", format_iso_timestamp(session.start),
+				format_iso_timestamp(end), session.duration_until(end).num_seconds())?;
+		}
+	}
+	Ok(())
+}
+
+pub fn records_as_csv(journal: &Journal, out: &mut dyn Write) -> io::Result<()> {
+	writeln!(out, "timestamp,action")?;
+	for record in journal.all_records()? {
+		writeln!(out, "{},{}", format_iso_timestamp(record.timestamp), record.action.name())?;
+	}
+	Ok(())
+}
+
+// A raw dump of every record, one JSON object per line. Records aren't paired into sessions,
+// so this works on logs with anomalies too.
+pub fn records_as_jsonl(journal: &Journal, out: &mut dyn Write) -> io::Result<()> {
+	for record in journal.all_records()? {
+		let mut line = format!("{{\"timestamp\": {}, \"action\": {}", json_string(&record.timestamp.format("%FT%TZ").to_string()),
+			json_string(record.action.name()));
+		if let Some(ref project) = record.project {
+			line.push_str(&format!(", \"project\": {}", json_string(project)));
+		}
+		if let Some(ref note) = record.note {
+			line.push_str(&format!(", \"note\": {}", json_string(note)));
+		}
+		writeln!(out, "{}}}", line)?;
+	}
+	Ok(())
+}
+
+// One VEVENT per completed session, titled with its project if it has one; open sessions are
+// left out. UIDs come from the start time, so importing the file again updates events instead
+// of duplicating them.
+pub fn sessions_as_ics(journal: &Journal, out: &mut dyn Write) -> io::Result<()> {
+	write!(out, "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//punch//punch//EN\r\n")?;
+	for session in session::pair_sessions(&journal.all_records()?) {
+		if let Some(end) = session.end {
+			let start = session.start.format("%Y%m%dT%H%M%SZ");
+			let summary = session.project.as_ref().map_or(String::from("Work session"), |project| ics_text(project));
+			write!(out, "BEGIN:VEVENT\r\nUID:{}@punch\r\nDTSTAMP:{}\r\nDTSTART:{}\r\nDTEND:{}\r\nSUMMARY:{}\r\nEND:VEVENT\r\n",
+				start, start, start, end.format("%Y%m%dT%H%M%SZ"), summary)?;
+		}
+	}
+	write!(out, "END:VCALENDAR\r\n")
+}
+
+pub fn format_iso_timestamp(timestamp: DateTime<UTC>) -> String {
+	timestamp.with_timezone(&Local).format("%FT%T%:z").to_string()
+}
+
+// RFC 5545 text values escape backslashes, commas, semicolons and newlines.
+fn ics_text(value: &str) -> String {
+	value.replace('\\', "\\\\").replace(',', "\\,").replace(';', "\\;").replace('\n', "\\n")
+}
+
+fn json_string(value: &str) -> String {
+	let mut escaped = String::from("\"");
+	for c in value.chars() {
+		match c {
+			'"' => escaped.push_str("\\\""),
+			'\\' => escaped.push_str("\\\\"),
+			'\n' => escaped.push_str("\\n"),
+			'\t' => escaped.push_str("\\t"),
+			c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+			c => escaped.push(c)
+		}
+	}
+	escaped.push('"');
+	escaped
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	use test_support::journal_with;
+	
+	// Checks the structure an ICS parser relies on: CRLF line endings, `NAME:value` lines and
+	// BEGIN/END blocks that nest and match. Returns the VEVENT count.
+	fn count_valid_ics_events(ics: &str) -> usize {
+		assert!(ics.ends_with("\r\n"));
+		let mut open_blocks = vec![];
+		let mut event_count = 0;
+		for line in ics.split_terminator("\r\n") {
+			assert!(!line.contains('\n') && !line.contains('\r'), "{:?}", line);
+			let (name, value) = line.split_at(line.find(':').unwrap_or_else(|| panic!("no value in {:?}", line)));
+			match name {
+				"BEGIN" => open_blocks.push(&value[1..]),
+				"END" => {
+					assert_eq!(open_blocks.pop(), Some(&value[1..]));
+					if value == ":VEVENT" {
+						event_count += 1;
+					}
+				},
+				_ => assert!(!open_blocks.is_empty(), "{:?} outside a block", line)
+			}
+		}
+		assert!(open_blocks.is_empty());
+		event_count
+	}
+	
+	#[test]
+	fn exports_completed_sessions_as_ics() {
+		let journal = journal_with(&["2024-03-04T09:00:00Z\tI\tproject=acme, inc; web", "2024-03-04T17:00:00Z\tO",
+			"2024-03-05T09:00:00Z\tI", "2024-03-05T17:00:00Z\tO\tnote=done", "2024-03-06T09:00:00Z\tI"]);
+		let mut out = vec![];
+		sessions_as_ics(&journal, &mut out).unwrap();
+		let ics = String::from_utf8(out).unwrap();
+		assert_eq!(count_valid_ics_events(&ics), 2);
+		assert!(ics.contains("SUMMARY:acme\\, inc\\; web\r\n"));
+		assert!(ics.contains("DTSTART:20240305T090000Z\r\nDTEND:20240305T170000Z\r\n"));
+		assert!(!ics.contains("20240306"));
+	}
+}
@@ -0,0 +1,355 @@
+// Just enough gzip (RFC 1952) and DEFLATE (RFC 1951) to read an archived log; nothing is
+// ever compressed by punch itself.
+
+const MAX_CODE_BITS: usize = 15;
+const LENGTH_BASES: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99,
+	115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA_BITS: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASES: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025,
+	1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11,
+	12, 12, 13, 13];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+const FLAG_HEADER_CRC: u8 = 2;
+const FLAG_EXTRA: u8 = 4;
+const FLAG_NAME: u8 = 8;
+const FLAG_COMMENT: u8 = 16;
+
+// Decompresses every member of a gzip file, checking each one's CRC and length.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+	let mut output = vec![];
+	let mut position = 0;
+	while position < data.len() {
+		// Like gzip itself, ignore zero bytes padding the file out after the last member.
+		if position > 0 && data[position..].iter().all(|b| *b == 0) {
+			break
+		}
+		position = skip_header(data, position)?;
+		let member_start = output.len();
+		let mut bits = BitReader::new(data, position);
+		inflate(&mut bits, &mut output)?;
+		position = bits.byte_position();
+		let trailer = data.get(position..position + 8).ok_or("Truncated gzip trailer")?;
+		if read_u32(&trailer[0..4]) != crc32(&output[member_start..]) {
+			return Err(String::from("gzip checksum doesn't match"))
+		}
+		if read_u32(&trailer[4..8]) != (output.len() - member_start) as u32 {
+			return Err(String::from("gzip length doesn't match"))
+		}
+		position += 8;
+	}
+	Ok(output)
+}
+
+pub fn is_gzip(data: &[u8]) -> bool {
+	data.starts_with(&[0x1f, 0x8b])
+}
+
+fn skip_header(data: &[u8], start: usize) -> Result<usize, String> {
+	let header = data.get(start..start + 10).ok_or("Truncated gzip header")?;
+	if !is_gzip(header) || header[2] != 8 {
+		return Err(String::from("Not a gzip file"))
+	}
+	let flags = header[3];
+	let mut position = start + 10;
+	if flags & FLAG_EXTRA != 0 {
+		let extra_length = data.get(position..position + 2).ok_or("Truncated gzip header")?;
+		position += 2 + (extra_length[0] as usize | (extra_length[1] as usize) << 8);
+	}
+	for flag in &[FLAG_NAME, FLAG_COMMENT] {
+		if flags & flag != 0 {
+			let terminator = data.get(position..).and_then(|rest| rest.iter().position(|b| *b == 0)).
+				ok_or("Truncated gzip header")?;
+			position += terminator + 1;
+		}
+	}
+	if flags & FLAG_HEADER_CRC != 0 {
+		position += 2;
+	}
+	if position > data.len() {
+		return Err(String::from("Truncated gzip header"))
+	}
+	Ok(position)
+}
+
+fn inflate(bits: &mut BitReader, output: &mut Vec<u8>) -> Result<(), String> {
+	loop {
+		let is_last_block = bits.read(1)? == 1;
+		match bits.read(2)? {
+			0 => copy_stored_block(bits, output)?,
+			1 => {
+				let (literals, distances) = fixed_codes();
+				inflate_block(bits, output, &literals, &distances)?
+			},
+			2 => {
+				let (literals, distances) = read_dynamic_codes(bits)?;
+				inflate_block(bits, output, &literals, &distances)?
+			},
+			_ => return Err(String::from("Invalid deflate block type"))
+		}
+		if is_last_block {
+			return Ok(())
+		}
+	}
+}
+
+fn copy_stored_block(bits: &mut BitReader, output: &mut Vec<u8>) -> Result<(), String> {
+	bits.align_to_byte();
+	let position = bits.byte_position();
+	let lengths = bits.data.get(position..position + 4).ok_or("Truncated stored block")?;
+	let length = lengths[0] as usize | (lengths[1] as usize) << 8;
+	if length != !(lengths[2] as usize | (lengths[3] as usize) << 8) & 0xffff {
+		return Err(String::from("Corrupt stored block length"))
+	}
+	let block = bits.data.get(position + 4..position + 4 + length).ok_or("Truncated stored block")?;
+	output.extend_from_slice(block);
+	bits.position = position + 4 + length;
+	Ok(())
+}
+
+fn inflate_block(bits: &mut BitReader, output: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<(), String> {
+	loop {
+		let symbol = literals.decode(bits)? as usize;
+		if symbol < 256 {
+			output.push(symbol as u8);
+			continue
+		}
+		if symbol == 256 {
+			return Ok(())
+		}
+		let length_index = symbol - 257;
+		if length_index >= LENGTH_BASES.len() {
+			return Err(String::from("Invalid length code"))
+		}
+		let length = LENGTH_BASES[length_index] as usize + bits.read(LENGTH_EXTRA_BITS[length_index] as u32)? as usize;
+		let distance_index = distances.decode(bits)? as usize;
+		if distance_index >= DISTANCE_BASES.len() {
+			return Err(String::from("Invalid distance code"))
+		}
+		let distance = DISTANCE_BASES[distance_index] as usize + bits.read(DISTANCE_EXTRA_BITS[distance_index] as u32)? as usize;
+		if distance > output.len() {
+			return Err(String::from("Distance too far back"))
+		}
+		// Byte by byte, as the copy may overlap the bytes it produces.
+		let copy_start = output.len() - distance;
+		for index in 0..length {
+			let byte = output[copy_start + index];
+			output.push(byte);
+		}
+	}
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+	let mut literal_lengths = [8u8; 288];
+	for length in literal_lengths.iter_mut().take(256).skip(144) {
+		*length = 9;
+	}
+	for length in literal_lengths.iter_mut().take(280).skip(256) {
+		*length = 7;
+	}
+	(Huffman::new(&literal_lengths), Huffman::new(&[5u8; 30]))
+}
+
+fn read_dynamic_codes(bits: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+	let literal_count = bits.read(5)? as usize + 257;
+	let distance_count = bits.read(5)? as usize + 1;
+	let code_length_count = bits.read(4)? as usize + 4;
+	let mut code_length_lengths = [0u8; 19];
+	for symbol in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+		code_length_lengths[*symbol] = bits.read(3)? as u8;
+	}
+	let code_lengths = Huffman::new(&code_length_lengths);
+
+	let mut lengths = vec![];
+	while lengths.len() < literal_count + distance_count {
+		let (length, repeat) = match code_lengths.decode(bits)? {
+			symbol @ 0..=15 => (symbol as u8, 1),
+			16 => (*lengths.last().ok_or("Repeated length with no previous length")?, 3 + bits.read(2)?),
+			17 => (0, 3 + bits.read(3)?),
+			_ => (0, 11 + bits.read(7)?)
+		};
+		for _ in 0..repeat {
+			lengths.push(length);
+		}
+	}
+	if lengths.len() > literal_count + distance_count {
+		return Err(String::from("Too many code lengths"))
+	}
+	Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+// Canonical Huffman codes, kept as the number of codes of each length and the symbols in
+// code order, which is all decoding one bit at a time needs.
+struct Huffman {
+	counts: [u16; MAX_CODE_BITS + 1],
+	symbols: Vec<u16>
+}
+
+impl Huffman {
+	fn new(lengths: &[u8]) -> Huffman {
+		let mut counts = [0u16; MAX_CODE_BITS + 1];
+		for length in lengths {
+			counts[*length as usize] += 1;
+		}
+		counts[0] = 0;
+		let mut offsets = [0u16; MAX_CODE_BITS + 1];
+		for length in 1..MAX_CODE_BITS {
+			offsets[length + 1] = offsets[length] + counts[length];
+		}
+		let mut symbols = vec![0u16; lengths.len()];
+		for (symbol, length) in lengths.iter().enumerate() {
+			if *length != 0 {
+				symbols[offsets[*length as usize] as usize] = symbol as u16;
+				offsets[*length as usize] += 1;
+			}
+		}
+		Huffman {
+			counts,
+			symbols
+		}
+	}
+
+	fn decode(&self, bits: &mut BitReader) -> Result<u16, String> {
+		let mut code: i32 = 0;
+		let mut first: i32 = 0;
+		let mut index: i32 = 0;
+		for length in 1..=MAX_CODE_BITS {
+			code |= bits.read(1)? as i32;
+			let count = self.counts[length] as i32;
+			if code - count < first {
+				return Ok(self.symbols[(index + code - first) as usize])
+			}
+			index += count;
+			first = (first + count) << 1;
+			code <<= 1;
+		}
+		Err(String::from("Invalid Huffman code"))
+	}
+}
+
+struct BitReader<'a> {
+	data: &'a [u8],
+	position: usize,
+	bit_buffer: u32,
+	bit_count: u32
+}
+
+impl<'a> BitReader<'a> {
+	fn new(data: &'a [u8], position: usize) -> BitReader<'a> {
+		BitReader {
+			data,
+			position,
+			bit_buffer: 0,
+			bit_count: 0
+		}
+	}
+
+	fn read(&mut self, count: u32) -> Result<u32, String> {
+		while self.bit_count < count {
+			let byte = *self.data.get(self.position).ok_or("Unexpected end of compressed data")?;
+			self.bit_buffer |= (byte as u32) << self.bit_count;
+			self.bit_count += 8;
+			self.position += 1;
+		}
+		let value = self.bit_buffer & ((1u32 << count) - 1);
+		self.bit_buffer >>= count;
+		self.bit_count -= count;
+		Ok(value)
+	}
+
+	fn align_to_byte(&mut self) {
+		self.bit_buffer = 0;
+		self.bit_count = 0;
+	}
+
+	// Whole bytes already buffered haven't been used yet, so they're given back.
+	fn byte_position(&self) -> usize {
+		self.position - (self.bit_count / 8) as usize
+	}
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+	bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+fn crc32(data: &[u8]) -> u32 {
+	let mut crc = !0u32;
+	for byte in data {
+		crc ^= *byte as u32;
+		for _ in 0..8 {
+			crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+		}
+	}
+	!crc
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Made with Python's zlib: "punch" at level 0, a log line with the fixed codes only, and ten
+	// days of records at level 9.
+	const STORED: [u8; 28] = [0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x01, 0x05, 0x00, 0xfa, 0xff,
+		0x70, 0x75, 0x6e, 0x63, 0x68, 0x58, 0x70, 0xe1, 0x2c, 0x05, 0x00, 0x00, 0x00];
+	const FIXED: [u8; 41] = [0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x33, 0x32, 0x30, 0x32, 0xd1,
+		0x35, 0x30, 0xd6, 0x35, 0x30, 0x09, 0x31, 0xb0, 0xb4, 0x32, 0x30, 0x00, 0xa2, 0x28, 0x4e, 0x4f, 0x2e, 0x00, 0x97,
+		0x31, 0x2b, 0xc0, 0x17, 0x00, 0x00, 0x00];
+	const DYNAMIC: [u8; 102] = [0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x6d, 0xca, 0xb9, 0x0d, 0x80,
+		0x30, 0x10, 0x05, 0xd1, 0x18, 0x7a, 0xb1, 0xf4, 0xbd, 0xeb, 0xbb, 0x03, 0x22, 0x27, 0x44, 0xf4, 0x5f, 0x08, 0x96,
+		0x08, 0x4c, 0x30, 0xd2, 0x44, 0xa3, 0x67, 0xb2, 0x14, 0xe4, 0x41, 0xf1, 0x56, 0x1f, 0xd2, 0xea, 0x39, 0xae, 0xd3,
+		0xf6, 0x8e, 0x75, 0xf8, 0xb7, 0xe7, 0xde, 0xc6, 0xda, 0x58, 0x3b, 0x6b, 0x67, 0x9d, 0x58, 0x27, 0xd6, 0x99, 0x75,
+		0x66, 0x5d, 0x58, 0x17, 0xd6, 0x95, 0x75, 0x65, 0xdd, 0x58, 0x37, 0xd6, 0x9d, 0x75, 0x47, 0x1d, 0x85, 0x7a, 0xed,
+		0xbf, 0x7e, 0x01, 0x3c, 0x90, 0xe8, 0xb5, 0xcc, 0x01, 0x00, 0x00];
+
+	fn dynamic_text() -> String {
+		(1..11).map(|day| format!("2024-03-{:02}T09:00:00Z\tI\n2024-03-{:02}T17:30:00Z\tO\n", day, day)).collect()
+	}
+
+	#[test]
+	fn decompresses_a_stored_block() {
+		assert_eq!(decompress(&STORED).unwrap(), b"punch");
+	}
+
+	#[test]
+	fn decompresses_a_fixed_block() {
+		assert_eq!(decompress(&FIXED).unwrap(), b"2024-03-04T09:00:00Z\tI\n");
+	}
+
+	#[test]
+	fn decompresses_a_dynamic_block() {
+		assert_eq!(decompress(&DYNAMIC).unwrap(), dynamic_text().as_bytes());
+	}
+
+	#[test]
+	fn concatenates_every_member() {
+		let data = [&STORED[..], &DYNAMIC[..]].concat();
+		let expected = format!("punch{}", dynamic_text());
+		assert_eq!(decompress(&data).unwrap(), expected.as_bytes());
+	}
+
+	#[test]
+	fn ignores_trailing_zero_padding() {
+		let data = [&STORED[..], &[0u8; 512][..]].concat();
+		assert_eq!(decompress(&data).unwrap(), b"punch");
+	}
+
+	#[test]
+	fn rejects_a_corrupt_checksum() {
+		let mut data = STORED;
+		data[20] ^= 1;
+		assert_eq!(decompress(&data), Err(String::from("gzip checksum doesn't match")));
+	}
+
+	#[test]
+	fn rejects_truncated_input() {
+		for length in 1..DYNAMIC.len() {
+			assert!(decompress(&DYNAMIC[..length]).is_err(), "{} bytes", length);
+		}
+	}
+
+	#[test]
+	fn rejects_data_that_isnt_gzip() {
+		assert_eq!(decompress(b"2024-03-04T09:00:00Z\tI\n"), Err(String::from("Not a gzip file")));
+	}
+}
@@ -0,0 +1,306 @@
+use std::char;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use chrono::DateTime;
+use chrono::UTC;
+
+use journal;
+use journal::Journal;
+use journal::PunchError;
+use record::Action;
+use record::Record;
+use session;
+
+const MAX_JSON_DEPTH: usize = 64;
+
+pub struct ImportedSession {
+	pub start: DateTime<UTC>,
+	pub end: DateTime<UTC>,
+	pub project: Option<String>
+}
+
+// Reads a JSON array of `{"start": ..., "end": ...}` objects with RFC 3339 timestamps and an
+// optional "project". Other keys are ignored.
+pub fn parse_sessions_json(text: &str) -> Result<Vec<ImportedSession>, String> {
+	let mut chars = text.chars().peekable();
+	let value = parse_value(&mut chars, 0)?;
+	skip_whitespace(&mut chars);
+	if chars.peek().is_some() {
+		return Err(String::from("Unexpected text after the JSON value"))
+	}
+	let items = match value {
+		JsonValue::Array(items) => items,
+		_ => return Err(String::from("Expected a JSON array of sessions"))
+	};
+
+	let mut sessions = vec![];
+	for (index, item) in items.iter().enumerate() {
+		let fields = match *item {
+			JsonValue::Object(ref fields) => fields,
+			_ => return Err(format!("Session {} isn't an object", index))
+		};
+		let start = timestamp_field(fields, "start").map_err(|e| format!("Session {}: {}", index, e))?;
+		let end = timestamp_field(fields, "end").map_err(|e| format!("Session {}: {}", index, e))?;
+		if end < start {
+			return Err(format!("Session {} ends before it starts", index))
+		}
+		let project = match fields.iter().find(|&(key, _)| key == "project") {
+			Some(&(_, JsonValue::String(ref project))) => Some(project.clone()),
+			Some(&(_, JsonValue::Null)) | None => None,
+			Some(_) => return Err(format!("Session {}: project must be a string", index))
+		};
+		sessions.push(ImportedSession {
+			start,
+			end,
+			project
+		});
+	}
+	sessions.sort_by_key(|session| session.start);
+	Ok(sessions)
+}
+
+// Merges the sessions into the log in chronological order and returns the records added. Nothing
+// is written if any session overlaps another one or a session already in the log, or when
+// `dry_run` is set.
+pub fn import_sessions(journal: &Journal, sessions: &[ImportedSession], dry_run: bool) -> Result<Vec<Record>, PunchError> {
+	let _lock = journal.lock()?;
+	let mut records = journal.records().map_err(|e| PunchError::Log(format!("Failed to read log: {}", e)))?;
+	// Archived sessions count too, or importing an old export again would duplicate them.
+	let existing_sessions = session::pair_sessions(&journal.all_records().
+		map_err(|e| PunchError::Log(format!("Failed to read log: {}", e)))?);
+	let now = UTC::now();
+
+	for (index, imported) in sessions.iter().enumerate() {
+		journal::ensure_not_in_the_future(imported.end)?;
+		if index > 0 && imported.start < sessions[index - 1].end {
+			return Err(PunchError::Log(format!("Imported sessions starting at {} and {} overlap",
+				sessions[index - 1].start.format("%FT%TZ"), imported.start.format("%FT%TZ"))))
+		}
+		for existing in &existing_sessions {
+			if imported.start < existing.end.unwrap_or(now) && existing.start < imported.end {
+				return Err(PunchError::Log(format!("Imported session starting at {} overlaps the logged one starting at {}",
+					imported.start.format("%FT%TZ"), existing.start.format("%FT%TZ"))))
+			}
+		}
+	}
+
+	let mut added = vec![];
+	for imported in sessions {
+		added.push(Record {
+			timestamp: imported.start,
+			action: Action::PunchIn,
+			project: imported.project.clone(),
+			note: None
+		});
+		added.push(Record {
+			timestamp: imported.end,
+			action: Action::PunchOut,
+			project: None,
+			note: None
+		});
+	}
+	if !dry_run {
+		records.extend(added.iter().cloned());
+		// Stable, so records at the same instant keep their order.
+		records.sort_by_key(|record| record.timestamp);
+		journal.replace_records(&records).map_err(|e| PunchError::Log(format!("Failed to rewrite log: {}", e)))?;
+	}
+	Ok(added)
+}
+
+fn timestamp_field(fields: &[(String, JsonValue)], key: &str) -> Result<DateTime<UTC>, String> {
+	match fields.iter().find(|&(field_key, _)| field_key == key) {
+		Some(&(_, JsonValue::String(ref text))) => DateTime::parse_from_rfc3339(text).map(|ts| ts.with_timezone(&UTC)).
+			map_err(|e| format!("Could not parse {} '{}': {}", key, text, e)),
+		Some(_) => Err(format!("{} must be a string", key)),
+		None => Err(format!("Missing {}", key))
+	}
+}
+
+enum JsonValue {
+	Null,
+	Bool,
+	Number,
+	String(String),
+	Array(Vec<JsonValue>),
+	Object(Vec<(String, JsonValue)>)
+}
+
+// Just enough JSON to read the import format; numbers and booleans are checked but not kept.
+// Nesting is limited so that a hostile file can't overflow the stack.
+fn parse_value(chars: &mut Peekable<Chars>, depth: usize) -> Result<JsonValue, String> {
+	if depth > MAX_JSON_DEPTH {
+		return Err(format!("JSON nested more than {} levels deep", MAX_JSON_DEPTH))
+	}
+	skip_whitespace(chars);
+	match chars.peek().cloned() {
+		Some('[') => {
+			chars.next();
+			let mut items = vec![];
+			skip_whitespace(chars);
+			if chars.peek() == Some(&']') {
+				chars.next();
+				return Ok(JsonValue::Array(items))
+			}
+			loop {
+				items.push(parse_value(chars, depth + 1)?);
+				skip_whitespace(chars);
+				match chars.next() {
+					Some(',') => continue,
+					Some(']') => return Ok(JsonValue::Array(items)),
+					_ => return Err(String::from("Expected ',' or ']' in array"))
+				}
+			}
+		},
+		Some('{') => {
+			chars.next();
+			let mut fields = vec![];
+			skip_whitespace(chars);
+			if chars.peek() == Some(&'}') {
+				chars.next();
+				return Ok(JsonValue::Object(fields))
+			}
+			loop {
+				skip_whitespace(chars);
+				let key = parse_string(chars)?;
+				skip_whitespace(chars);
+				if chars.next() != Some(':') {
+					return Err(format!("Expected ':' after key '{}'", key))
+				}
+				fields.push((key, parse_value(chars, depth + 1)?));
+				skip_whitespace(chars);
+				match chars.next() {
+					Some(',') => continue,
+					Some('}') => return Ok(JsonValue::Object(fields)),
+					_ => return Err(String::from("Expected ',' or '}' in object"))
+				}
+			}
+		},
+		Some('"') => parse_string(chars).map(JsonValue::String),
+		Some(c) if c == '-' || c.is_ascii_digit() => {
+			let mut number = String::new();
+			while let Some(&c) = chars.peek() {
+				if !(c.is_ascii_digit() || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E') {
+					break
+				}
+				number.push(c);
+				chars.next();
+			}
+			number.parse::<f64>().map(|_| JsonValue::Number).map_err(|_| format!("Invalid number '{}'", number))
+		},
+		Some(_) => {
+			let mut word = String::new();
+			while let Some(&c) = chars.peek() {
+				if !c.is_ascii_alphabetic() {
+					break
+				}
+				word.push(c);
+				chars.next();
+			}
+			match word.as_str() {
+				"true" | "false" => Ok(JsonValue::Bool),
+				"null" => Ok(JsonValue::Null),
+				_ => Err(format!("Unexpected '{}'", word))
+			}
+		},
+		None => Err(String::from("Unexpected end of JSON"))
+	}
+}
+
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+	if chars.next() != Some('"') {
+		return Err(String::from("Expected a string"))
+	}
+	let mut text = String::new();
+	loop {
+		match chars.next() {
+			Some('"') => return Ok(text),
+			Some('\\') => match chars.next() {
+				Some('n') => text.push('\n'),
+				Some('t') => text.push('\t'),
+				Some('r') => text.push('\r'),
+				Some('b') => text.push('\u{8}'),
+				Some('f') => text.push('\u{c}'),
+				Some('u') => text.push(parse_unicode_escape(chars)?),
+				Some(c) => text.push(c),
+				None => return Err(String::from("Unterminated string"))
+			},
+			Some(c) => text.push(c),
+			None => return Err(String::from("Unterminated string"))
+		}
+	}
+}
+
+// Characters outside the Basic Multilingual Plane, e.g. emoji, are escaped as a UTF-16
+// surrogate pair, `\ud83d\ude80`.
+fn parse_unicode_escape(chars: &mut Peekable<Chars>) -> Result<char, String> {
+	let code = parse_hex4(chars)?;
+	if !(0xd800..0xdc00).contains(&code) {
+		return char::from_u32(code).ok_or(format!("Invalid escape '\\u{:04x}'", code))
+	}
+	if chars.next() != Some('\\') || chars.next() != Some('u') {
+		return Err(format!("Unpaired surrogate '\\u{:04x}'", code))
+	}
+	let low = parse_hex4(chars)?;
+	if !(0xdc00..0xe000).contains(&low) {
+		return Err(format!("Unpaired surrogate '\\u{:04x}'", code))
+	}
+	char::from_u32(0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00)).ok_or(format!("Invalid escape '\\u{:04x}'", code))
+}
+
+fn parse_hex4(chars: &mut Peekable<Chars>) -> Result<u32, String> {
+	let code: String = chars.by_ref().take(4).collect();
+	if code.len() != 4 || !code.chars().all(|c| c.is_ascii_hexdigit()) {
+		return Err(format!("Invalid escape '\\u{}'", code))
+	}
+	u32::from_str_radix(&code, 16).map_err(|_| format!("Invalid escape '\\u{}'", code))
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+	while chars.peek().is_some_and(|c| c.is_whitespace()) {
+		chars.next();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_sessions_in_start_order() {
+		let sessions = parse_sessions_json(r#"[
+			{"start": "2024-03-05T09:00:00Z", "end": "2024-03-05T17:00:00Z", "project": null},
+			{"start": "2024-03-04T09:00:00+01:00", "end": "2024-03-04T12:00:00+01:00", "project": "acme", "rate": 1.5e2}
+		]"#).unwrap();
+		assert_eq!(sessions.len(), 2);
+		assert_eq!(sessions[0].start.format("%FT%TZ").to_string(), "2024-03-04T08:00:00Z");
+		assert_eq!(sessions[0].project, Some(String::from("acme")));
+		assert_eq!(sessions[1].project, None);
+	}
+
+	#[test]
+	fn decodes_surrogate_pairs() {
+		let sessions = parse_sessions_json(r#"[{"start": "2024-03-04T09:00:00Z", "end": "2024-03-04T10:00:00Z",
+			"project": "launch \ud83d\ude80"}]"#).unwrap();
+		assert_eq!(sessions[0].project, Some(String::from("launch \u{1f680}")));
+	}
+
+	#[test]
+	fn rejects_unpaired_surrogates() {
+		assert!(parse_sessions_json(r#"[{"start": "2024-03-04T09:00:00Z", "end": "2024-03-04T10:00:00Z",
+			"project": "\ud83d"}]"#).is_err());
+	}
+
+	#[test]
+	fn rejects_deeply_nested_json() {
+		let text = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+		assert_eq!(parse_sessions_json(&text).err(), Some(format!("JSON nested more than {} levels deep", MAX_JSON_DEPTH)));
+	}
+
+	#[test]
+	fn rejects_sessions_ending_before_they_start() {
+		assert_eq!(parse_sessions_json(r#"[{"start": "2024-03-04T10:00:00Z", "end": "2024-03-04T09:00:00Z"}]"#).err(),
+			Some(String::from("Session 0 ends before it starts")));
+	}
+}
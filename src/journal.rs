@@ -1,56 +1,1208 @@
+use std::cmp;
 use std::env;
+use std::fmt;
+use std::fs;
 use std::fs::DirBuilder;
 use std::fs::File;
 use std::fs::OpenOptions;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
 use std::io::Write;
 use std::io;
+use std::path::Path;
 use std::path::PathBuf;
-use std::process;
 use std::str;
+use std::thread;
+use std::time;
 
-pub fn get_conf_file(read: bool, append: bool) -> io::Result<File> {
+use chrono;
+use chrono::DateTime;
+use chrono::Local;
+use chrono::UTC;
+use fs2::FileExt;
+
+use gzip;
+use record::Action;
+use record::LOG_HEADER;
+use record::Record;
+use record::is_record_line;
+use session;
+use session::DailyDuration;
+use session::Session;
+
+const SEEK_CHUNK_LENGTH: usize = 4096;
+// Records are short, so each probe of the binary search reads this much rather than a whole
+// default-sized buffer.
+const SEARCH_BUFFER_LENGTH: usize = 128;
+const LOCK_TIMEOUT_MILLIS: u64 = 5000;
+const LOCK_RETRY_MILLIS: u64 = 50;
+const FUTURE_TOLERANCE_SECONDS: i64 = 60;
+const REPAIR_HINT: &str = "the log may be corrupt, run `punch repair`";
+
+pub fn get_log_path() -> PathBuf {
 	let mut conf_file = PathBuf::new();
     conf_file.push(env::home_dir().unwrap());
     conf_file.push(".punch");
     conf_file.push("punch.log");
-    
-    OpenOptions::new().read(read).append(append).open(conf_file)
-}
-
-pub fn append_to_file(data: &[u8], f: &mut File) {
-	match f.write_all(data) {
-    	Ok(_) => {},
-    	Err(e) => println!("Failed to write data to log: {}", e)
-    }
-}
-
-pub fn ensure_log_file_exists() -> io::Result<()> {
-    let mut conf_dir = PathBuf::new();
-    conf_dir.push(env::home_dir().unwrap());
-    conf_dir.push(".punch");
-    let config_path = conf_dir.as_path();
-
-    let mut conf_file_builder = PathBuf::from(config_path);
-    conf_file_builder.push("punch.log");
-
-    let mut dir_builder = DirBuilder::new();
-    dir_builder.recursive(true);
-    
-    try!(dir_builder.create(config_path));
-
-    let conf_file = conf_file_builder.as_path();
-    match OpenOptions::new().create(true).write(true).open(conf_file) {
-    	Ok(_) => Ok(()),
-    	Err(e) => Err(e)
-    }	
-}
-
-pub fn exit_if_log_file_cannot_be_created() {
-    match ensure_log_file_exists() {
-    	Ok(_) => {},
-    	Err(e) => {
-    		println!("Couldn't create punch log: {}.\nExiting.", e);
-			process::exit(1)
-    	}
-    }
-}
\ No newline at end of file
+    conf_file
+}
+
+// Synced before returning, so that a punch reported as recorded survives a power loss.
+pub fn append_to_file(data: &[u8], f: &mut File) -> io::Result<()> {
+	f.write_all(data)?;
+	f.flush()?;
+	f.sync_data()
+}
+
+#[derive(Debug)]
+pub enum PunchError {
+	AlreadyPunchedIn,
+	AlreadyPunchedOut,
+	AlreadyOnBreak,
+	NotOnBreak,
+	OtherProjectOpen(Option<String>),
+	EarlierThanLastRecord(DateTime<UTC>),
+	InTheFuture(DateTime<UTC>),
+	Log(String)
+}
+
+impl fmt::Display for PunchError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			PunchError::AlreadyPunchedIn => write!(f, "Already punched in, punch out first!"),
+			PunchError::AlreadyPunchedOut => write!(f, "Already punched out, punch in first!"),
+			PunchError::AlreadyOnBreak => write!(f, "Already on a break, resume first!"),
+			PunchError::NotOnBreak => write!(f, "Not on a break, take one first!"),
+			PunchError::OtherProjectOpen(Some(ref project)) => write!(f, "Punched in to project {}, not that one!", project),
+			PunchError::OtherProjectOpen(None) => write!(f, "Punched in without a project, not to that one!"),
+			PunchError::EarlierThanLastRecord(last_timestamp) =>
+				write!(f, "Records must stay in order, but the one before is at {}", last_timestamp.with_timezone(&Local)),
+			PunchError::InTheFuture(timestamp) =>
+				write!(f, "{} is in the future, check the date and the system clock", timestamp.with_timezone(&Local)),
+			PunchError::Log(ref e) => write!(f, "{}", e)
+		}
+	}
+}
+
+// Allows for a little clock difference, e.g. between machines sharing the log.
+pub fn ensure_not_in_the_future(timestamp: DateTime<UTC>) -> Result<(), PunchError> {
+	if timestamp > chrono::UTC::now() + chrono::Duration::seconds(FUTURE_TOLERANCE_SECONDS) {
+		return Err(PunchError::InTheFuture(timestamp))
+	}
+	Ok(())
+}
+
+// Checks that `action` may follow `last_action`: breaks can only be taken while punched in and
+// resumed while on one. An empty log only accepts a punch-in.
+pub fn check_transition(last_action: Action, action: Action) -> Result<(), PunchError> {
+	match (last_action, action) {
+		(Action::Unset, Action::PunchIn) | (Action::PunchOut, Action::PunchIn) => Ok(()),
+		(_, Action::PunchIn) => Err(PunchError::AlreadyPunchedIn),
+		(Action::PunchOut, _) | (Action::Unset, _) => Err(PunchError::AlreadyPunchedOut),
+		(Action::BreakStart, Action::BreakStart) => Err(PunchError::AlreadyOnBreak),
+		(Action::BreakStart, _) => Ok(()),
+		(_, Action::BreakEnd) => Err(PunchError::NotOnBreak),
+		_ => Ok(())
+	}
+}
+
+// Where the log stands, from its last record. A log with no records counts as punched out,
+// but commands can tell it apart to say so.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq)]
+pub enum LogState {
+	Empty,
+	OpenSession,
+	Closed
+}
+
+impl LogState {
+	pub fn of(last_record: Option<&Record>) -> LogState {
+		match last_record {
+			None => LogState::Empty,
+			Some(record) if record.action.leaves_session_open() => LogState::OpenSession,
+			Some(_) => LogState::Closed
+		}
+	}
+}
+
+pub struct Journal {
+	path: PathBuf,
+	dry_run: bool
+}
+
+impl Journal {
+	pub fn new(path: PathBuf) -> Journal {
+		Journal {
+			path,
+			dry_run: false
+		}
+	}
+	
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+	
+	// In a dry run, everything is read and checked as usual, but what would have been written
+	// to the log is printed instead.
+	pub fn set_dry_run(&mut self, dry_run: bool) {
+		self.dry_run = dry_run;
+	}
+	
+	pub fn is_dry_run(&self) -> bool {
+		self.dry_run
+	}
+	
+	// A new or empty log gets the header up front, so that records appended to it are never
+	// mistaken for an unmigrated v1 log. Under dry-run nothing is created, and a missing log
+	// reads as empty.
+	pub fn ensure_exists(&self) -> io::Result<()> {
+		if self.dry_run {
+			return Ok(())
+		}
+		if let Some(dir) = self.path.parent() {
+			DirBuilder::new().recursive(true).create(dir)?;
+		}
+		OpenOptions::new().create(true).write(true).truncate(false).open(&self.path)?;
+		let mut log_file = self.open(false, true)?;
+		if log_file.metadata()?.len() == 0 {
+			append_to_file(format!("{}\n", LOG_HEADER).as_bytes(), &mut log_file)?;
+		}
+		Ok(())
+	}
+	
+	// Writers hold an exclusive lock and readers a shared one, so concurrent invocations can't
+	// interleave partial records. The lock is released when the returned `File` is dropped.
+	pub fn open(&self, read: bool, append: bool) -> io::Result<File> {
+		let log_file = OpenOptions::new().read(read).append(append).open(&self.path)?;
+		if append {
+			log_file.lock_exclusive()?;
+		}
+		else {
+			log_file.lock_shared()?;
+		}
+		Ok(log_file)
+	}
+	
+	// Like `open` for reading, except that under dry-run a log that hasn't been created yet
+	// reads as empty.
+	pub fn open_shared(&self) -> io::Result<LogFile> {
+		match self.open(true, false) {
+			Ok(log_file) => Ok(LogFile::Opened(log_file)),
+			Err(ref e) if self.dry_run && e.kind() == io::ErrorKind::NotFound => Ok(LogFile::Missing),
+			Err(e) => Err(e)
+		}
+	}
+	
+	// Every record ends with a newline, so a log that doesn't was cut short mid-write or edited by
+	// hand, and its last record can't be trusted. Readers refuse it rather than misparse it.
+	fn open_for_reading(&self) -> io::Result<LogFile> {
+		let mut log_file = self.open_shared()?;
+		let file_len = log_file.seek(SeekFrom::End(0))?;
+		if file_len > 0 {
+			let mut last_byte = [0u8; 1];
+			log_file.seek(SeekFrom::Start(file_len - 1))?;
+			log_file.read_exact(&mut last_byte)?;
+			if last_byte[0] != b'\n' {
+				return Err(io::Error::new(io::ErrorKind::InvalidData,
+					"the log is corrupt, its last record is incomplete or has no newline; run `punch repair --fix`"))
+			}
+		}
+		log_file.seek(SeekFrom::Start(0))?;
+		Ok(log_file)
+	}
+	
+	pub fn replace_contents(&self, data: &[u8]) -> io::Result<()> {
+		if self.dry_run {
+			println!("Dry run, would rewrite {} as:\n{}", self.path.display(), String::from_utf8_lossy(data).trim_end());
+			return Ok(())
+		}
+		let replacement_path = self.path.with_extension("log.tmp");
+		
+		let mut replacement = File::create(&replacement_path)?;
+		replacement.write_all(data)?;
+		replacement.sync_all()?;
+		fs::rename(replacement_path, &self.path)
+	}
+	
+	pub fn backup(&self) -> io::Result<PathBuf> {
+		let backup_path = self.path.with_extension("log.bak");
+		if self.dry_run {
+			println!("Dry run, would copy the log to {}", backup_path.display());
+			return Ok(backup_path)
+		}
+		fs::copy(&self.path, &backup_path)?;
+		Ok(backup_path)
+	}
+	
+	pub fn replace_records(&self, records: &[Record]) -> io::Result<()> {
+		let mut contents = format!("{}\n", LOG_HEADER);
+		for record in records {
+			contents.push_str(&record.to_log_line());
+		}
+		self.replace_contents(contents.as_bytes())
+	}
+	
+	// Runs before every command, so a log already in the current format costs one line's read.
+	pub fn ensure_current_format(&self) -> Result<(), String> {
+		if self.is_in_current_format()? {
+			return Ok(())
+		}
+		// Checked again under the lock, in case another punch migrated the log in the meantime.
+		let _lock = self.lock().map_err(|e| e.to_string())?;
+		if self.is_in_current_format()? {
+			return Ok(())
+		}
+		let mut contents = String::new();
+		if let Err(e) = self.open_shared().and_then(|mut f| f.read_to_string(&mut contents)) {
+			return Err(format!("Failed to read log: {}", e))
+		}
+		
+		let mut records = vec![];
+		for line in contents.lines().filter(|line| is_record_line(line)) {
+			records.push(Record::from_log_line(line)?);
+		}
+		// Readers handle v1 lines too, so a dry run can carry on without the rewrite, and needn't
+		// print the whole log to say so.
+		if self.dry_run {
+			println!("Dry run, would migrate {} with {} records to the current format", self.path.display(), records.len());
+			return Ok(())
+		}
+		match self.replace_records(&records) {
+			Ok(_) => Ok(()),
+			Err(e) => Err(format!("Failed to rewrite log: {}", e))
+		}
+	}
+	
+	fn is_in_current_format(&self) -> Result<bool, String> {
+		let mut first_line = String::new();
+		if let Err(e) = self.open_shared().and_then(|f| BufReader::new(f).read_line(&mut first_line)) {
+			return Err(format!("Failed to read log: {}", e))
+		}
+		// Empty only under dry-run, which doesn't write the header; there's nothing to migrate.
+		Ok(first_line.is_empty() || first_line.trim_end() == LOG_HEADER)
+	}
+	
+	pub fn records(&self) -> io::Result<Vec<Record>> {
+		let log_file = self.open_for_reading()?;
+		let mut records = vec![];
+		
+		for line in BufReader::new(log_file).lines() {
+			let line = line?;
+			if !is_record_line(&line) {
+				continue
+			}
+			match Record::from_log_line(&line) {
+				Ok(record) => records.push(record),
+				Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}; {}", e, REPAIR_HINT)))
+			}
+		}
+		Ok(records)
+	}
+	
+	pub fn archive_path(&self) -> PathBuf {
+		self.path.with_extension("log.gz")
+	}
+	
+	// Older records can be moved into a gzipped copy of the log next to it, which is only ever
+	// read. Commands that rewrite the log leave it alone, so it should hold completed sessions.
+	pub fn archived_records(&self) -> io::Result<Vec<Record>> {
+		let archive_path = self.archive_path();
+		let mut data = vec![];
+		match File::open(&archive_path) {
+			Ok(mut f) => f.read_to_end(&mut data)?,
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+			Err(e) => return Err(e)
+		};
+		let contents = gzip::decompress(&data).and_then(|bytes| String::from_utf8(bytes).map_err(|e| e.to_string())).
+			map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Failed to read {}: {}", archive_path.display(), e)))?;
+		contents.lines().filter(|line| is_record_line(line)).
+			map(|line| Record::from_log_line(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+				format!("{} in {}", e, archive_path.display())))).
+			collect()
+	}
+	
+	// The archived records followed by the log's own, for reports over the whole history.
+	pub fn all_records(&self) -> io::Result<Vec<Record>> {
+		let mut records = self.archived_records()?;
+		records.extend(self.records()?);
+		Ok(records)
+	}
+	
+	pub fn records_from_end(&self) -> io::Result<RecordsFromEnd<LogFile>> {
+		self.open_for_reading().and_then(RecordsFromEnd::new)
+	}
+	
+	pub fn last_record(&self) -> Result<Option<Record>, String> {
+		match self.records_from_end() {
+			Ok(mut records) => records.next().transpose(),
+			Err(e) => Err(format!("Failed to open log: {}", e))
+		}
+	}
+	
+	pub fn state(&self) -> Result<LogState, String> {
+		self.last_record().map(|record| LogState::of(record.as_ref()))
+	}
+	
+	pub fn last_action(&self) -> Result<Action, String> {
+		self.last_record().map(|record| record.map_or(Action::Unset, |record| record.action))
+	}
+	
+	// The records of the most recent session, oldest first: its punch-in, any breaks and its
+	// punch-out if it has one.
+	pub fn last_session_records(&self) -> Result<Vec<Record>, String> {
+		let mut session_records = vec![];
+		let records = self.records_from_end().map_err(|e| format!("Failed to open log: {}", e))?;
+		for read_attempt in records {
+			let record = read_attempt?;
+			if record.action == Action::PunchOut && !session_records.is_empty() {
+				break
+			}
+			let is_punch_in = record.action == Action::PunchIn;
+			session_records.push(record);
+			if is_punch_in {
+				break
+			}
+		}
+		session_records.reverse();
+		Ok(session_records)
+	}
+	
+	// Held for the whole read-check-write of a punch, so that two punches at once can't both
+	// pass the check and then append. The log itself is locked only per read or write, which
+	// isn't enough on its own. Released when dropped, or by the OS if the process exits. A dry
+	// run writes nothing, so it doesn't take the lock or create its file.
+	pub fn lock(&self) -> Result<JournalLock, PunchError> {
+		if self.dry_run {
+			return Ok(JournalLock {
+				file: None
+			})
+		}
+		let lock_path = self.path.with_extension("lock");
+		let lock_file = OpenOptions::new().write(true).create(true).truncate(false).open(&lock_path).
+			map_err(|e| PunchError::Log(format!("Failed to open lock file {}: {}", lock_path.display(), e)))?;
+		let mut waited_millis = 0;
+		while lock_file.try_lock_exclusive().is_err() {
+			if waited_millis >= LOCK_TIMEOUT_MILLIS {
+				return Err(PunchError::Log(format!("Timed out waiting for another punch to release {}", lock_path.display())))
+			}
+			thread::sleep(time::Duration::from_millis(LOCK_RETRY_MILLIS));
+			waited_millis += LOCK_RETRY_MILLIS;
+		}
+		Ok(JournalLock {
+			file: Some(lock_file)
+		})
+	}
+	
+	pub fn append_record(&self, record: &Record) -> Result<(), PunchError> {
+		let _lock = self.lock()?;
+		let last_record = self.last_record().map_err(PunchError::Log)?;
+		self.append_record_after(last_record.as_ref(), record)
+	}
+	
+	// Callers hold the lock and pass the last record they already read, so it isn't read twice.
+	// Summaries binary search the log by timestamp, so records must be appended in
+	// chronological order.
+	fn append_record_after(&self, last_record: Option<&Record>, record: &Record) -> Result<(), PunchError> {
+		ensure_not_in_the_future(record.timestamp)?;
+		if let Some(last_record) = last_record {
+			if record.timestamp < last_record.timestamp {
+				return Err(PunchError::EarlierThanLastRecord(last_record.timestamp))
+			}
+		}
+		if self.dry_run {
+			println!("Dry run, would append: {}", record.to_log_line().trim_end());
+			return Ok(())
+		}
+		let mut log_file = self.open(false, true).map_err(|e| PunchError::Log(format!("Failed to open log: {}", e)))?;
+		append_to_file(record.to_log_line().as_bytes(), &mut log_file).
+			map_err(|e| PunchError::Log(format!("Failed to write to log: {}", e)))
+	}
+	
+	// Moves the last record to `timestamp`, which mustn't be earlier than the record before it.
+	pub fn amend_last_timestamp(&self, timestamp: DateTime<UTC>) -> Result<Record, PunchError> {
+		let _lock = self.lock()?;
+		ensure_not_in_the_future(timestamp)?;
+		let mut records = self.records().map_err(|e| PunchError::Log(format!("Failed to read log: {}", e)))?;
+		if records.len() >= 2 {
+			let previous_timestamp = records[records.len() - 2].timestamp;
+			if timestamp < previous_timestamp {
+				return Err(PunchError::EarlierThanLastRecord(previous_timestamp))
+			}
+		}
+		let amended_record = match records.last_mut() {
+			Some(last_record) => {
+				last_record.timestamp = timestamp;
+				last_record.clone()
+			},
+			None => return Err(PunchError::Log(String::from("The log has no records to amend")))
+		};
+		self.replace_records(&records).map_err(|e| PunchError::Log(format!("Failed to rewrite log: {}", e)))?;
+		Ok(amended_record)
+	}
+	
+	// Changes the action of the record `offset` places from the end (0 being the last one), as
+	// long as it still fits between the records around it.
+	pub fn amend_action(&self, offset: usize, action: Action) -> Result<Record, PunchError> {
+		let _lock = self.lock()?;
+		let mut records = self.records().map_err(|e| PunchError::Log(format!("Failed to read log: {}", e)))?;
+		if offset >= records.len() {
+			return Err(PunchError::Log(format!("The log has only {} record(s)", records.len())))
+		}
+		let index = records.len() - 1 - offset;
+		if index == 0 && check_transition(Action::PunchOut, action).is_err() {
+			return Err(PunchError::Log(format!("The log can't start with {}", action)))
+		}
+		if index > 0 && check_transition(records[index - 1].action, action).is_err() {
+			return Err(PunchError::Log(format!("{} can't follow {}", action, records[index - 1].action)))
+		}
+		if let Some(next_record) = records.get(index + 1) {
+			if check_transition(action, next_record.action).is_err() {
+				return Err(PunchError::Log(format!("{} can't follow {}", next_record.action, action)))
+			}
+		}
+		records[index].action = action;
+		self.replace_records(&records).map_err(|e| PunchError::Log(format!("Failed to rewrite log: {}", e)))?;
+		Ok(records[index].clone())
+	}
+	
+	// Drops the last record if it's a punch-in, as if the session had never been started, and
+	// returns it. Completed sessions and sessions with breaks are left alone.
+	pub fn cancel_open_session(&self) -> Result<Option<Record>, PunchError> {
+		let _lock = self.lock()?;
+		let mut records = self.records().map_err(|e| PunchError::Log(format!("Failed to read log: {}", e)))?;
+		match records.last() {
+			Some(record) if record.action == Action::PunchIn => {},
+			_ => return Ok(None)
+		}
+		let cancelled = records.pop();
+		self.replace_records(&records).map_err(|e| PunchError::Log(format!("Failed to rewrite log: {}", e)))?;
+		Ok(cancelled)
+	}
+	
+	// Drops every record before `cutoff` and returns how many went. A session the cutoff falls
+	// into would lose its punch-in, so nothing is removed then. Under dry-run only the count is
+	// worked out.
+	pub fn remove_records_before(&self, cutoff: DateTime<UTC>) -> Result<usize, PunchError> {
+		let _lock = self.lock()?;
+		let mut records = self.records().map_err(|e| PunchError::Log(format!("Failed to read log: {}", e)))?;
+		let removed_count = records.iter().take_while(|record| record.timestamp < cutoff).count();
+		if removed_count == 0 {
+			return Ok(0)
+		}
+		if records[removed_count - 1].action != Action::PunchOut {
+			let session_start = records[..removed_count].iter().rev().find(|record| record.action == Action::PunchIn).
+				map_or(records[removed_count - 1].timestamp, |record| record.timestamp);
+			return Err(PunchError::Log(format!("the session punched in at {} isn't over by then, pick a date outside it",
+				session_start.with_timezone(&Local).format("%F %H:%M"))))
+		}
+		if !self.dry_run {
+			records.drain(..removed_count);
+			self.replace_records(&records).map_err(|e| PunchError::Log(format!("Failed to rewrite log: {}", e)))?;
+		}
+		Ok(removed_count)
+	}
+	
+	pub fn punch_in(&self, project: Option<&str>) -> Result<(), PunchError> {
+		let _lock = self.lock()?;
+		let last_record = self.last_record_allowing(Action::PunchIn)?;
+		self.append_record_after(last_record.as_ref(), &Record {
+			timestamp: chrono::UTC::now(),
+			action: Action::PunchIn,
+			project: project.map(String::from),
+			note: None
+		})
+	}
+	
+	// Undoes an accidental punch-out by dropping it, so the last session carries on, and returns
+	// the punch-in that session started with. A note on the punch-out moves to the punch-in
+	// rather than being lost.
+	pub fn resume(&self) -> Result<Record, PunchError> {
+		let _lock = self.lock()?;
+		let session_records = self.last_session_records().map_err(PunchError::Log)?;
+		match (session_records.first(), session_records.last()) {
+			(_, Some(last_record)) if last_record.action != Action::PunchOut => return Err(PunchError::AlreadyPunchedIn),
+			(Some(first_record), _) if first_record.action == Action::PunchIn => {},
+			_ => return Err(PunchError::Log(String::from("there's no session to resume")))
+		}
+		let mut records = self.records().map_err(|e| PunchError::Log(format!("Failed to read log: {}", e)))?;
+		let punch_out = records.pop();
+		let punch_in_index = records.len() + 1 - session_records.len();
+		if let Some(note) = punch_out.and_then(|punch_out| punch_out.note) {
+			let punch_in = &mut records[punch_in_index];
+			punch_in.note = Some(match punch_in.note.take() {
+				Some(earlier_note) => format!("{}; {}", earlier_note, note),
+				None => note
+			});
+		}
+		self.replace_records(&records).map_err(|e| PunchError::Log(format!("Failed to rewrite log: {}", e)))?;
+		Ok(records[punch_in_index].clone())
+	}
+	
+	// Punches in again carrying over the project of the last session and the note it was
+	// closed with. There has to be a completed session to carry over.
+	pub fn punch_in_carrying_over(&self) -> Result<(), PunchError> {
+		let _lock = self.lock()?;
+		let session_records = self.last_session_records().map_err(PunchError::Log)?;
+		let (project, note) = match (session_records.first(), session_records.last()) {
+			(_, Some(last_record)) if last_record.action.leaves_session_open() => return Err(PunchError::AlreadyPunchedIn),
+			(Some(first_record), Some(last_record)) if first_record.action == Action::PunchIn =>
+				(first_record.project.clone(), last_record.note.clone()),
+			_ => return Err(PunchError::Log(String::from("there's no session to carry over")))
+		};
+		self.append_record_after(session_records.last(), &Record {
+			timestamp: chrono::UTC::now(),
+			action: Action::PunchIn,
+			project,
+			note
+		})
+	}
+	
+	// Splits a session still open from an earlier day at each local midnight since, with a
+	// punch-out at 23:59:59 and a punch-in for the same project at 00:00:00, so every day gets
+	// its own session. A break in progress carries on after the punch-in. Does nothing once the
+	// open session started today, so running it again the same day is harmless.
+	pub fn split_open_session_at_midnight(&self) -> Result<Vec<Record>, PunchError> {
+		let _lock = self.lock()?;
+		let session_records = self.last_session_records().map_err(PunchError::Log)?;
+		let mut previous_record = match session_records.last() {
+			Some(last_record) if last_record.action.leaves_session_open() => last_record.clone(),
+			_ => return Ok(vec![])
+		};
+		let project = session_records.first().and_then(|first_record| first_record.project.clone());
+		let on_break = previous_record.action == Action::BreakStart;
+		let today = Local::today();
+		let mut date = previous_record.timestamp.with_timezone(&Local).date();
+		let mut added = vec![];
+		while date < today {
+			let midnight = date.succ().and_hms(0, 0, 0).with_timezone(&UTC);
+			let mut split_records = vec![
+				Record {
+					timestamp: cmp::max(midnight - chrono::Duration::seconds(1), previous_record.timestamp),
+					action: Action::PunchOut,
+					project: None,
+					note: None
+				},
+				Record {
+					timestamp: midnight,
+					action: Action::PunchIn,
+					project: project.clone(),
+					note: None
+				}
+			];
+			if on_break {
+				split_records.push(Record {
+					timestamp: midnight,
+					action: Action::BreakStart,
+					project: None,
+					note: None
+				});
+			}
+			for record in split_records {
+				self.append_record_after(Some(&previous_record), &record)?;
+				previous_record = record.clone();
+				added.push(record);
+			}
+			date = date.succ();
+		}
+		Ok(added)
+	}
+	
+	// Appends `record` whatever state the log is in, e.g. to repair history by hand. It still has
+	// to come after the last record. Returns the state check that was skipped if it would have failed.
+	pub fn append_ignoring_state(&self, record: &Record) -> Result<Option<PunchError>, PunchError> {
+		let _lock = self.lock()?;
+		let last_record = self.last_record().map_err(PunchError::Log)?;
+		let overridden = check_transition(last_record.as_ref().map_or(Action::Unset, |record| record.action), record.action).err();
+		self.append_record_after(last_record.as_ref(), record)?;
+		Ok(overridden)
+	}
+	
+	pub fn punch_out(&self, note: Option<&str>) -> Result<(), PunchError> {
+		self.punch_out_at(chrono::UTC::now(), note)
+	}
+	
+	pub fn punch_out_at(&self, timestamp: DateTime<UTC>, note: Option<&str>) -> Result<(), PunchError> {
+		let _lock = self.lock()?;
+		let last_record = self.last_record_allowing(Action::PunchOut)?;
+		self.append_record_after(last_record.as_ref(), &Record {
+			timestamp,
+			action: Action::PunchOut,
+			project: None,
+			note: note.map(String::from)
+		})
+	}
+	
+	pub fn start_break(&self) -> Result<(), PunchError> {
+		self.append_action(Action::BreakStart)
+	}
+	
+	pub fn end_break(&self) -> Result<(), PunchError> {
+		self.append_action(Action::BreakEnd)
+	}
+	
+	// Records a break that has already happened, ending now, as a break and a resume.
+	pub fn record_break(&self, duration: chrono::Duration) -> Result<(), PunchError> {
+		let _lock = self.lock()?;
+		let last_record = self.last_record_allowing(Action::BreakStart)?;
+		let now = chrono::UTC::now();
+		let break_start = Record {
+			timestamp: now - duration,
+			action: Action::BreakStart,
+			project: None,
+			note: None
+		};
+		self.append_record_after(last_record.as_ref(), &break_start)?;
+		self.append_record_after(Some(&break_start), &Record {
+			timestamp: now,
+			action: Action::BreakEnd,
+			project: None,
+			note: None
+		})
+	}
+	
+	fn append_action(&self, action: Action) -> Result<(), PunchError> {
+		let _lock = self.lock()?;
+		let last_record = self.last_record_allowing(action)?;
+		self.append_record_after(last_record.as_ref(), &Record {
+			timestamp: chrono::UTC::now(),
+			action,
+			project: None,
+			note: None
+		})
+	}
+	
+	pub fn open_session(&self) -> Result<Option<Session>, String> {
+		let session_records = self.last_session_records()?;
+		Ok(session::pair_sessions(&session_records).pop().filter(|session| session.end.is_none()))
+	}
+	
+	// Lets `punch out --project` make sure it closes the session it was meant for.
+	pub fn ensure_open_project_is(&self, project: &str) -> Result<(), PunchError> {
+		match self.open_session().map_err(PunchError::Log)? {
+			Some(ref session) if session.project.as_ref().is_some_and(|open_project| open_project == project) => Ok(()),
+			Some(session) => Err(PunchError::OtherProjectOpen(session.project)),
+			None => Err(PunchError::AlreadyPunchedOut)
+		}
+	}
+	
+	fn last_record_allowing(&self, action: Action) -> Result<Option<Record>, PunchError> {
+		let last_record = self.last_record().map_err(PunchError::Log)?;
+		check_transition(last_record.as_ref().map_or(Action::Unset, |record| record.action), action)?;
+		Ok(last_record)
+	}
+	
+	pub fn sessions_between(&self, start_time: DateTime<UTC>, end_time: DateTime<UTC>) -> Result<Vec<Session>, String> {
+		self.sessions_and_anomalies_between(start_time, end_time).map(|(sessions, _)| sessions)
+	}
+	
+	// Sessions are attributed to the day they started on, so a session is included when its
+	// punch-in falls in `[start_time, end_time)` even if the punch-out comes later. A session
+	// still open at the end of the log has no end. Records before the first punch-in belong to
+	// a session that started before `start_time`, so aren't counted as anomalies.
+	pub fn sessions_and_anomalies_between(&self, start_time: DateTime<UTC>, end_time: DateTime<UTC>) -> Result<(Vec<Session>, usize), String> {
+		let mut log_file = match self.open_for_reading() {
+			Ok(f) => f,
+			Err(e) => return Err(format!("Failed to open log: {}", e))
+		};
+		// The archive only holds records older than the log's, so it's only worth decompressing
+		// when the range starts before the log does.
+		let log_start = match log_file.seek(SeekFrom::End(0)) {
+			Ok(file_len) => first_record_timestamp_at_or_after(&mut log_file, 0, file_len)?,
+			Err(e) => return Err(format!("Failed to find the end of the log: {}", e))
+		};
+		let archived_records = match log_start {
+			Some(timestamp) if start_time >= timestamp => vec![],
+			_ => self.archived_records().map_err(|e| e.to_string())?
+		};
+		let archived_records = archived_records.into_iter().filter(|record| record.timestamp >= start_time).map(Ok);
+		seek_to_first_record_since(&mut log_file, start_time)?;
+		let log_records = BufReader::new(log_file).lines().filter_map(|line| match line {
+			Ok(ref text) if !is_record_line(text) => None,
+			Ok(text) => Some(Record::from_log_line(&text).map_err(|e| format!("{}; {}", e, REPAIR_HINT))),
+			Err(e) => Some(Err(e.to_string()))
+		});
+		
+		let mut records = vec![];
+		for read_attempt in archived_records.chain(log_records) {
+			let record = read_attempt?;
+			if record.action == Action::PunchIn && record.timestamp >= end_time {
+				break
+			}
+			if record.action == Action::PunchIn || !records.is_empty() {
+				records.push(record);
+			}
+		}
+		Ok(session::pair_sessions_counting_anomalies(&records))
+	}
+	
+	// Open sessions count up to now.
+	pub fn durations_since(&self, start_time: DateTime<UTC>) -> Result<Vec<DailyDuration>, String> {
+		self.daily_durations_between(start_time, chrono::UTC::now(), None)
+	}
+	
+	// An open session counts up to `end_time`, or up to now if that's earlier. With a `project`,
+	// only sessions punched in to it count, so sessions without a project are left out.
+	pub fn daily_durations_between(&self, start_time: DateTime<UTC>, end_time: DateTime<UTC>, project: Option<&str>) -> Result<Vec<DailyDuration>, String> {
+		let until = cmp::min(chrono::UTC::now(), end_time);
+		let sessions = self.sessions_between(start_time, end_time)?;
+		Ok(session::daily_durations(sessions.iter().filter(|session| project.is_none() || session.project.as_deref() == project).
+			map(|session| (session.date(), session.duration_until(until)))))
+	}
+}
+
+pub struct JournalLock {
+	file: Option<File>
+}
+
+impl Drop for JournalLock {
+	fn drop(&mut self) {
+		if let Some(ref file) = self.file {
+			let _ = file.unlock();
+		}
+	}
+}
+
+// The log as opened for reading. It can only be missing under dry-run, and then reads as empty.
+pub enum LogFile {
+	Opened(File),
+	Missing
+}
+
+impl Read for LogFile {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		match *self {
+			LogFile::Opened(ref mut file) => file.read(buf),
+			LogFile::Missing => Ok(0)
+		}
+	}
+}
+
+impl Seek for LogFile {
+	fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+		match *self {
+			LogFile::Opened(ref mut file) => file.seek(position),
+			LogFile::Missing => Ok(0)
+		}
+	}
+}
+
+// Iterates over the log from the newest record to the oldest, reading the file backwards
+// in chunks so that summaries only touch the tail they need.
+// Generic over the reader so that it also works on an in-memory `Cursor`.
+pub struct RecordsFromEnd<R = File> {
+	file: R,
+	position: u64,
+	pending: Vec<u8>
+}
+
+impl<R: Read + Seek> RecordsFromEnd<R> {
+	pub fn new(mut file: R) -> io::Result<RecordsFromEnd<R>> {
+		let file_len = file.seek(SeekFrom::End(0))?;
+		Ok(RecordsFromEnd {
+			file,
+			position: file_len,
+			pending: vec![]
+		})
+	}
+	
+	fn read_previous_chunk(&mut self) -> io::Result<()> {
+		let chunk_start = self.position.saturating_sub(SEEK_CHUNK_LENGTH as u64);
+		let mut chunk = vec![0u8; (self.position - chunk_start) as usize];
+		self.file.seek(SeekFrom::Start(chunk_start))?;
+		self.file.read_exact(&mut chunk)?;
+		chunk.extend_from_slice(&self.pending);
+		self.pending = chunk;
+		self.position = chunk_start;
+		Ok(())
+	}
+}
+
+impl<R: Read + Seek> Iterator for RecordsFromEnd<R> {
+	type Item = Result<Record, String>;
+	
+	fn next(&mut self) -> Option<Result<Record, String>> {
+		loop {
+			let line = match self.pending.iter().rposition(|b| *b == b'\n') {
+				Some(index) => {
+					let line = self.pending.split_off(index + 1);
+					self.pending.truncate(index);
+					line
+				},
+				None if self.position == 0 => {
+					if self.pending.is_empty() {
+						return None
+					}
+					self.pending.split_off(0)
+				},
+				None => {
+					if let Err(e) = self.read_previous_chunk() {
+						return Some(Err(format!("Failed to read log: {}", e)))
+					}
+					continue
+				}
+			};
+			let text = match str::from_utf8(&line) {
+				Ok(text) => text,
+				Err(e) => return Some(Err(format!("Could not read record: {}", e)))
+			};
+			if !is_record_line(text) {
+				continue
+			}
+			
+			return Some(Record::from_log_line(text).map_err(|e| format!("{}; {}", e, REPAIR_HINT)))
+		}
+	}
+}
+
+// Records are appended in chronological order, so the first record at or after `start_time`
+// can be found by binary searching byte offsets instead of reading every earlier record.
+fn seek_to_first_record_since<R: Read + Seek>(f: &mut R, start_time: DateTime<UTC>) -> Result<(), String> {
+	let file_len = match f.seek(SeekFrom::End(0)) {
+		Ok(len) => len,
+		Err(e) => return Err(format!("Failed to find the end of the log: {}", e))
+	};
+	
+	let mut low = 0;
+	let mut high = file_len;
+	while low < high {
+		let mid = low + (high - low) / 2;
+		match first_record_timestamp_at_or_after(f, mid, file_len)? {
+			Some(timestamp) if timestamp < start_time => low = mid + 1,
+			_ => high = mid
+		}
+	}
+	
+	let record_start = line_start_at_or_after(f, low)?;
+	match f.seek(SeekFrom::Start(record_start)) {
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("Failed to seek: {}", e))
+	}
+}
+
+fn first_record_timestamp_at_or_after<R: Read + Seek>(f: &mut R, position: u64, file_len: u64) -> Result<Option<DateTime<UTC>>, String> {
+	let record_start = line_start_at_or_after(f, position)?;
+	if record_start >= file_len {
+		return Ok(None)
+	}
+	if let Err(e) = f.seek(SeekFrom::Start(record_start)) {
+		return Err(format!("Failed to seek: {}", e))
+	}
+	
+	for line in BufReader::with_capacity(SEARCH_BUFFER_LENGTH, f).lines() {
+		match line {
+			Ok(ref text) if !is_record_line(text) => continue,
+			Ok(text) => return Record::from_log_line(&text).map(|record| Some(record.timestamp)),
+			Err(e) => return Err(format!("Failed to read record: {}", e))
+		}
+	}
+	Ok(None)
+}
+
+fn line_start_at_or_after<R: Read + Seek>(f: &mut R, position: u64) -> Result<u64, String> {
+	if position == 0 {
+		return Ok(0)
+	}
+	
+	let mut skipped = vec![];
+	let read_result = f.seek(SeekFrom::Start(position - 1)).
+		and_then(|_| BufReader::with_capacity(SEARCH_BUFFER_LENGTH, &mut *f).read_until(b'\n', &mut skipped));
+	match read_result {
+		Ok(read) => Ok(position - 1 + read as u64),
+		Err(e) => Err(format!("Failed to read log: {}", e))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	use std::io::Cursor;
+	
+	use chrono::Datelike;
+	use chrono::TimeZone;
+	
+	use test_support::journal_with;
+	
+	fn contents_of(journal: &Journal) -> String {
+		fs::read_to_string(journal.path()).unwrap()
+	}
+	
+	fn record_at(timestamp: DateTime<UTC>, action: Action) -> Record {
+		Record {
+			timestamp,
+			action,
+			project: None,
+			note: None
+		}
+	}
+	
+	// One record a minute from the start of 2024, alternating punch-ins and punch-outs.
+	fn synthetic_log(record_count: usize) -> (Vec<Record>, String) {
+		let start = UTC.ymd(2024, 1, 1).and_hms(0, 0, 0);
+		let records: Vec<Record> = (0..record_count).map(|i| {
+			record_at(start + chrono::Duration::minutes(i as i64), if i % 2 == 0 { Action::PunchIn } else { Action::PunchOut })
+		}).collect();
+		let mut contents = format!("{}\n", LOG_HEADER);
+		for record in &records {
+			contents.push_str(&record.to_log_line());
+		}
+		(records, contents)
+	}
+	
+	// Counts the reads made through it, to show how little of the log a search touches.
+	struct CountingReader {
+		inner: Cursor<Vec<u8>>,
+		bytes_read: usize,
+		read_count: usize
+	}
+	
+	impl Read for CountingReader {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			let read = self.inner.read(buf)?;
+			self.bytes_read += read;
+			self.read_count += 1;
+			Ok(read)
+		}
+	}
+	
+	impl Seek for CountingReader {
+		fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+			self.inner.seek(position)
+		}
+	}
+	
+	#[test]
+	fn one_punch_out_is_a_closed_log_without_a_session() {
+		let journal = journal_with(&["2024-03-04T17:00:00Z\tO"]);
+		assert_eq!(journal.state().unwrap(), LogState::Closed);
+		assert_eq!(journal.last_session_records().unwrap().len(), 1);
+		assert!(journal.open_session().unwrap().is_none());
+	}
+	
+	#[test]
+	fn reads_100k_records_from_the_end() {
+		let (mut records, contents) = synthetic_log(100_000);
+		let read: Vec<Record> = RecordsFromEnd::new(Cursor::new(contents.into_bytes())).unwrap().
+			collect::<Result<Vec<Record>, String>>().unwrap();
+		records.reverse();
+		assert_eq!(read, records);
+	}
+	
+	#[test]
+	fn binary_search_reads_a_few_kb_of_50k_records() {
+		let (records, contents) = synthetic_log(50_000);
+		let probe_count = 64 - (contents.len() as u64).leading_zeros() as usize;
+		let mut reader = CountingReader {
+			inner: Cursor::new(contents.into_bytes()),
+			bytes_read: 0,
+			read_count: 0
+		};
+		seek_to_first_record_since(&mut reader, records[40_000].timestamp).unwrap();
+		let (bytes_read, read_count) = (reader.bytes_read, reader.read_count);
+		let mut next_line = String::new();
+		BufReader::new(&mut reader).read_line(&mut next_line).unwrap();
+		assert_eq!(Record::from_log_line(next_line.trim_end()).unwrap(), records[40_000]);
+		// Each probe reads to the next line start and then that line, and the final seek finds
+		// the line start once more.
+		assert!(read_count <= 2 * probe_count + 1, "{} reads for {} probes", read_count, probe_count);
+		assert!(bytes_read <= read_count * SEARCH_BUFFER_LENGTH, "read {} bytes", bytes_read);
+	}
+	
+	#[test]
+	fn binary_search_past_the_last_record_finds_nothing() {
+		let (records, contents) = synthetic_log(1000);
+		let mut reader = Cursor::new(contents.into_bytes());
+		seek_to_first_record_since(&mut reader, records[999].timestamp + chrono::Duration::minutes(1)).unwrap();
+		let mut rest = String::new();
+		reader.read_to_string(&mut rest).unwrap();
+		assert_eq!(rest, "");
+	}
+	
+	#[test]
+	fn concurrent_appends_both_land_whole() {
+		let journal = journal_with(&[]);
+		let timestamp = UTC.ymd(2024, 3, 4).and_hms(9, 0, 0);
+		let writers: Vec<thread::JoinHandle<Result<(), PunchError>>> = vec![Action::PunchIn, Action::PunchOut].into_iter().map(|action| {
+			let path = journal.path().to_path_buf();
+			thread::spawn(move || Journal::new(path).append_record(&record_at(timestamp, action)))
+		}).collect();
+		for writer in writers {
+			writer.join().unwrap().unwrap();
+		}
+		let records = journal.records().unwrap();
+		assert_eq!(records.len(), 2);
+		assert!(records.iter().all(|record| record.timestamp == timestamp));
+	}
+	
+	#[test]
+	fn refuses_a_record_earlier_than_the_last() {
+		let journal = journal_with(&["2024-03-04T09:00:00Z\tI"]);
+		let before = contents_of(&journal);
+		match journal.append_record(&record_at(UTC.ymd(2024, 3, 4).and_hms(8, 0, 0), Action::PunchOut)) {
+			Err(PunchError::EarlierThanLastRecord(_)) => {},
+			other => panic!("expected the record to be refused, got {:?}", other)
+		}
+		assert_eq!(contents_of(&journal), before);
+	}
+	
+	#[test]
+	fn checks_transitions() {
+		assert!(check_transition(Action::Unset, Action::PunchIn).is_ok());
+		assert!(check_transition(Action::PunchIn, Action::BreakStart).is_ok());
+		assert!(check_transition(Action::BreakStart, Action::PunchOut).is_ok());
+		assert!(check_transition(Action::BreakEnd, Action::PunchOut).is_ok());
+		match check_transition(Action::PunchIn, Action::PunchIn) {
+			Err(PunchError::AlreadyPunchedIn) => {},
+			other => panic!("expected AlreadyPunchedIn, got {:?}", other)
+		}
+		match check_transition(Action::Unset, Action::PunchOut) {
+			Err(PunchError::AlreadyPunchedOut) => {},
+			other => panic!("expected AlreadyPunchedOut, got {:?}", other)
+		}
+		match check_transition(Action::PunchOut, Action::BreakStart) {
+			Err(PunchError::AlreadyPunchedOut) => {},
+			other => panic!("expected AlreadyPunchedOut, got {:?}", other)
+		}
+		match check_transition(Action::BreakStart, Action::BreakStart) {
+			Err(PunchError::AlreadyOnBreak) => {},
+			other => panic!("expected AlreadyOnBreak, got {:?}", other)
+		}
+		match check_transition(Action::PunchIn, Action::BreakEnd) {
+			Err(PunchError::NotOnBreak) => {},
+			other => panic!("expected NotOnBreak, got {:?}", other)
+		}
+	}
+	
+	#[test]
+	fn amends_the_last_timestamp() {
+		let journal = journal_with(&["2024-03-04T09:00:00Z\tI", "2024-03-04T17:00:00Z\tO"]);
+		let amended_time = UTC.ymd(2024, 3, 4).and_hms(16, 30, 0);
+		journal.amend_last_timestamp(amended_time).unwrap();
+		assert_eq!(journal.last_record().unwrap().map(|record| record.timestamp), Some(amended_time));
+		match journal.amend_last_timestamp(UTC.ymd(2024, 3, 4).and_hms(8, 0, 0)) {
+			Err(PunchError::EarlierThanLastRecord(_)) => {},
+			other => panic!("expected the amendment to be refused, got {:?}", other)
+		}
+		assert_eq!(journal.last_record().unwrap().map(|record| record.timestamp), Some(amended_time));
+	}
+	
+	#[test]
+	fn append_to_a_read_only_file_fails() {
+		let journal = journal_with(&[]);
+		let mut log_file = File::open(journal.path()).unwrap();
+		assert!(append_to_file(b"2024-03-04T09:00:00Z\tI\n", &mut log_file).is_err());
+		assert_eq!(contents_of(&journal), format!("{}\n", LOG_HEADER));
+	}
+	
+	#[test]
+	fn sums_durations_per_day_between_two_times() {
+		let at = |day: u32, hour: u32| Local.ymd(2024, 3, day).and_hms(hour, 0, 0).with_timezone(&UTC).format("%FT%TZ").to_string();
+		let lines = [
+			format!("{}\tI", at(4, 9)), format!("{}\tO", at(4, 12)),
+			format!("{}\tI", at(4, 13)), format!("{}\tO", at(4, 17)),
+			format!("{}\tI", at(5, 9)), format!("{}\tO", at(5, 11)),
+			format!("{}\tI", at(6, 9)), format!("{}\tO", at(6, 10))
+		];
+		let journal = journal_with(&lines.iter().map(String::as_str).collect::<Vec<&str>>());
+		let start_time = Local.ymd(2024, 3, 4).and_hms(0, 0, 0).with_timezone(&UTC);
+		let end_time = Local.ymd(2024, 3, 6).and_hms(0, 0, 0).with_timezone(&UTC);
+		let daily_durations = journal.daily_durations_between(start_time, end_time, None).unwrap();
+		let days: Vec<(u32, i64)> = daily_durations.iter().map(|daily_duration| (daily_duration.date.day(), daily_duration.duration.num_hours())).collect();
+		assert_eq!(days, vec![(4, 7), (5, 2)]);
+	}
+	
+	#[test]
+	fn refuses_a_record_in_the_future() {
+		let journal = journal_with(&[]);
+		match journal.append_record(&record_at(UTC.ymd(2099, 1, 1).and_hms(0, 0, 0), Action::PunchIn)) {
+			Err(PunchError::InTheFuture(_)) => {},
+			other => panic!("expected the record to be refused, got {:?}", other)
+		}
+		assert!(journal.records().unwrap().is_empty());
+	}
+	
+	#[test]
+	fn cancels_an_open_session() {
+		let journal = journal_with(&["2024-03-04T09:00:00Z\tI", "2024-03-04T17:00:00Z\tO", "2024-03-05T09:00:00Z\tI"]);
+		let cancelled = journal.cancel_open_session().unwrap();
+		assert_eq!(cancelled.map(|record| record.timestamp), Some(UTC.ymd(2024, 3, 5).and_hms(9, 0, 0)));
+		assert_eq!(journal.records().unwrap().len(), 2);
+	}
+	
+	#[test]
+	fn leaves_a_closed_session_alone_on_cancel() {
+		let journal = journal_with(&["2024-03-04T09:00:00Z\tI", "2024-03-04T17:00:00Z\tO"]);
+		let before = contents_of(&journal);
+		assert!(journal.cancel_open_session().unwrap().is_none());
+		assert_eq!(contents_of(&journal), before);
+	}
+	
+	#[test]
+	fn appended_records_can_be_read_back_straight_away() {
+		let journal = journal_with(&[]);
+		let record = Record {
+			project: Some(String::from("acme")),
+			..record_at(UTC.ymd(2024, 3, 4).and_hms(9, 0, 0), Action::PunchIn)
+		};
+		journal.append_record(&record).unwrap();
+		assert_eq!(journal.records().unwrap(), vec![record.clone()]);
+		assert_eq!(journal.last_record().unwrap(), Some(record));
+	}
+	
+	#[test]
+	fn resumes_a_punched_out_session() {
+		let journal = journal_with(&["2024-03-04T09:00:00Z\tI\tproject=acme", "2024-03-04T12:00:00Z\tO\tnote=lunch"]);
+		let punch_in = journal.resume().unwrap();
+		assert_eq!(punch_in.timestamp, UTC.ymd(2024, 3, 4).and_hms(9, 0, 0));
+		assert_eq!(punch_in.note, Some(String::from("lunch")));
+		assert_eq!(journal.records().unwrap(), vec![punch_in]);
+		assert_eq!(journal.state().unwrap(), LogState::OpenSession);
+	}
+	
+	#[test]
+	fn refuses_to_resume_an_open_session() {
+		let journal = journal_with(&["2024-03-04T09:00:00Z\tI"]);
+		match journal.resume() {
+			Err(PunchError::AlreadyPunchedIn) => {},
+			other => panic!("expected AlreadyPunchedIn, got {:?}", other)
+		}
+		assert_eq!(journal.records().unwrap().len(), 1);
+	}
+	
+	#[test]
+	fn refuses_to_carry_over_without_a_session() {
+		for lines in [vec![], vec!["2024-03-04T17:00:00Z\tO"]] {
+			let journal = journal_with(&lines);
+			match journal.punch_in_carrying_over() {
+				Err(PunchError::Log(_)) => {},
+				other => panic!("expected nothing to carry over, got {:?}", other)
+			}
+			assert_eq!(journal.records().unwrap().len(), lines.len());
+		}
+	}
+	
+	#[test]
+	fn carries_over_the_project_and_note() {
+		let journal = journal_with(&["2024-03-04T09:00:00Z\tI\tproject=acme", "2024-03-04T12:00:00Z\tO\tnote=halfway"]);
+		journal.punch_in_carrying_over().unwrap();
+		let punch_in = journal.last_record().unwrap().unwrap();
+		assert_eq!(punch_in.action, Action::PunchIn);
+		assert_eq!(punch_in.project, Some(String::from("acme")));
+		assert_eq!(punch_in.note, Some(String::from("halfway")));
+	}
+	
+	#[test]
+	fn reads_the_archive_only_for_a_range_starting_before_the_log() {
+		let journal = journal_with(&["2024-03-04T09:00:00Z\tI", "2024-03-04T17:00:00Z\tO"]);
+		fs::write(journal.archive_path(), b"not gzip").unwrap();
+		let first_record_time = UTC.ymd(2024, 3, 4).and_hms(9, 0, 0);
+		let sessions = journal.sessions_between(first_record_time, UTC.ymd(2024, 3, 5).and_hms(0, 0, 0)).unwrap();
+		assert_eq!(sessions.len(), 1);
+		assert!(journal.sessions_between(first_record_time - chrono::Duration::seconds(1), first_record_time).is_err());
+	}
+}
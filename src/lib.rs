@@ -0,0 +1,49 @@
+extern crate chrono;
+extern crate fs2;
+extern crate toml;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+
+pub mod config;
+pub mod export;
+pub mod import;
+pub mod journal;
+pub mod record;
+pub mod session;
+
+mod gzip;
+#[cfg(test)]
+mod test_support;
+
+use std::io;
+
+use chrono::DateTime;
+use chrono::UTC;
+
+pub use config::Config;
+pub use journal::Journal;
+pub use journal::LogState;
+pub use journal::PunchError;
+pub use record::Action;
+pub use record::Record;
+pub use session::Break;
+pub use session::DailyDuration;
+pub use session::Session;
+
+// Shortcuts for working with the log `punch` itself uses, in ~/.punch/punch.log.
+pub fn read_records() -> io::Result<Vec<Record>> {
+	default_journal().records()
+}
+
+pub fn append_record(record: &Record) -> Result<(), PunchError> {
+	default_journal().append_record(record)
+}
+
+pub fn daily_durations_between(start_time: DateTime<UTC>, end_time: DateTime<UTC>, project: Option<&str>) -> Result<Vec<DailyDuration>, String> {
+	default_journal().daily_durations_between(start_time, end_time, project)
+}
+
+fn default_journal() -> Journal {
+	Journal::new(journal::get_log_path())
+}
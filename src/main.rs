@@ -1,82 +1,503 @@
 extern crate clap;
 extern crate chrono;
+extern crate punch;
 
-mod journal;
+#[cfg(test)]
+#[path = "test_support.rs"]
+mod test_support;
 
+use std::cmp;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
 use std::fs::File;
+use std::io;
+use std::io::IsTerminal;
 use std::io::Read;
-use std::io::Seek;
-use std::io::SeekFrom;
-use std::ops::Sub;
+use std::io::Write;
 use std::ops::Add;
+use std::ops::Sub;
 use std::process;
-use std::str;
+use std::str::FromStr;
+use std::thread;
+use std::time;
 
-use clap::{Arg, App, AppSettings, SubCommand};
+use clap::{Arg, ArgGroup, App, AppSettings, Shell, SubCommand};
 
 use chrono::Datelike;
 use chrono::DateTime;
-use chrono::Timelike;
+use chrono::Local;
 use chrono::TimeZone;
 use chrono::UTC;
 
-const RECORD_LENGTH: usize = 22;
+use punch::config;
+use punch::export;
+use punch::import;
+use punch::journal;
+use punch::record;
+use punch::session;
+use punch::{Action, Config, DailyDuration, Journal, LogState, PunchError, Record, Session};
+
+const DEFAULT_MAX_SESSION_HOURS: f64 = 16.0;
+const WEEKDAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const GUARD_FAILURE_EXIT_CODE: i32 = 2;
+const LONG_DAY_SECONDS: i64 = 8 * 3600;
+const SHORT_DAY_SECONDS: i64 = 4 * 3600;
+const PROGRESS_BAR_WIDTH: usize = 10;
+const WATCH_INTERVAL_MILLIS: u64 = 1000;
 
 #[derive(Debug)]
-#[derive(PartialEq)]
-enum Action {
-	PunchIn,
-	PunchOut,
-	Unset
+enum RoundingMode {
+	Nearest,
+	Up,
+	Down
 }
 
 #[derive(Debug)]
-struct Record {
-	timestamp: DateTime<UTC>,
-	action: Action
+struct Rounding {
+	increment_seconds: i64,
+	mode: RoundingMode
 }
 
-#[derive(Debug)]
-struct DailyDuration {
-	date: chrono::date::Date<UTC>,
-	duration: chrono::Duration
+#[derive(Clone, Copy)]
+enum DurationStyle {
+	Short,
+	Long,
+	Decimal,
+	WithDays
 }
 
+struct Rate {
+	per_hour: f64,
+	currency: String
+}
+
+struct SummaryOptions {
+	duration_style: DurationStyle,
+	by_project: bool,
+	include_open_session: bool,
+	target_seconds: Option<i64>,
+	rounding: Option<Rounding>,
+	rate: Option<Rate>,
+	project: Option<String>,
+	excluded_weekdays: Vec<chrono::Weekday>,
+	summary_only: bool,
+	plain: bool,
+	tsv: bool,
+	hours_only: bool,
+	fill_days: bool,
+	show_idle: bool,
+	total_label: String,
+	color: bool
+}
 
 fn main() {
-	journal::exit_if_log_file_cannot_be_created();
-	
-    let args = App::new("Punch").
-	    about("A simple time tracker app").
-	    version("0.1").
-        setting(AppSettings::ArgRequiredElseHelp).
-        subcommand(SubCommand::with_name("in").about("Punch in")).
-        subcommand(SubCommand::with_name("out").about("Punch out")).
-        subcommand(SubCommand::with_name("card").about("Display state").
-	        arg(Arg::with_name("week").long("week").short("w").help("Display summary for the last week")).
-	        arg(Arg::with_name("mtd").long("mtd").short("m").help("Display summary for the month to date"))).
-        get_matches();
+    let args = build_cli().get_matches();
+	let mut journal = Journal::new(journal::get_log_path());
+	journal.set_dry_run(args.is_present("dry-run"));
+	// The one place deciding whether any output gets ANSI colors.
+	let color = should_color(if args.is_present("no-color") { Some("never") } else { args.value_of("color") });
+	if let Err(e) = journal.ensure_exists() {
+		println!("Couldn't create punch log: {}.\nExiting.", e);
+		process::exit(1)
+	}
+	// Repair and doctor read v1 and v2 records alike, and have to work on a log too damaged to migrate.
+	let inspects_log = matches!(args.subcommand_name(), Some("repair") | Some("doctor"));
+	if !inspects_log {
+		if let Err(e) = journal.ensure_current_format() {
+			println!("Couldn't migrate punch log: {}.\nExiting.", e);
+			process::exit(1)
+		}
+	}
+	let mut config = match config::read_config() {
+		Ok(config) => config,
+		Err(e) => {
+			println!("Couldn't load config: {}.\nExiting.", e);
+			process::exit(1)
+		}
+	};
+
+	if let Some(timezone) = args.value_of("timezone") {
+		config.timezone = String::from(timezone);
+	}
+	match config.timezone.as_str() {
+		// Local times are rendered through the C library, which honours TZ as long as it's
+		// set before the first conversion.
+		"utc" => env::set_var("TZ", "UTC"),
+		"local" => {},
+		other => {
+			println!("Invalid timezone '{}' in config, expected local or utc.\nExiting.", other);
+			process::exit(1)
+		}
+	}
+	if parse_weekday(&config.week_start).is_none() {
+		println!("Invalid week_start '{}' in config, expected one of {}.\nExiting.", config.week_start, WEEKDAY_NAMES.join(", "));
+		process::exit(1)
+	}
+	let weekend: Vec<chrono::Weekday> = config.weekend.iter().map(|day| match parse_weekday(day) {
+		Some(weekday) => weekday,
+		None => {
+			println!("Invalid weekend day '{}' in config, expected one of {}.\nExiting.", day, WEEKDAY_NAMES.join(", "));
+			process::exit(1)
+		}
+	}).collect();
 
+	// The last session is read once, for `punch in`, `punch status` and the checks below when they
+	// run, and again only if a check changed the log. Commands that inspect a possibly damaged
+	// log don't split it.
+	let splits_at_midnight = config.split_at_midnight && !matches!(args.subcommand_name(),
+		Some("config") | Some("repair") | Some("doctor") | Some("check") | Some("completions"));
+	let runs_idle_check = match args.subcommand() {
+		("out", _) | ("toggle", _) | ("cancel", _) | ("config", _) | ("repair", _) | ("doctor", _) | ("check", _) |
+		("export", _) | ("stats", _) | ("list", _) | ("log", _) | ("completions", _) => false,
+		("status", Some(specifier)) => !specifier.is_present("short"),
+		_ => true
+	};
+	let reads_last_session = splits_at_midnight || runs_idle_check || matches!(args.subcommand_name(), Some("in") | Some("status"));
+	let mut session_records = if reads_last_session { read_last_session_records(&journal) } else { vec![] };
+	if splits_at_midnight && split_open_session_at_midnight(&journal) {
+		session_records = read_last_session_records(&journal);
+	}
+	if runs_idle_check && close_idle_session(&journal, &session_records, config.idle_hours, args.is_present("auto-out")) {
+		session_records = read_last_session_records(&journal);
+	}
 
 	match args.subcommand() {
+		("config", Some(specifier)) => {
+			if let ("set", Some(setting)) = specifier.subcommand() {
+				let key = setting.value_of("key").unwrap_or("");
+				if let Err(e) = config::set_config_value(key, setting.value_of("value").unwrap_or("")) {
+					println!("Couldn't update config: {}.\nExiting.", e);
+					process::exit(1)
+				}
+			}
+			else if specifier.is_present("show") || specifier.subcommand_matches("show").is_some() {
+				print_config(&config)
+			}
+			else {
+				println!("{}", specifier.usage())
+			}
+		},
 		("card", Some(specifier)) => {
+			let target_hours = match specifier.value_of("month-target") {
+				Some(hours) => Some(exit_if_number_is_invalid::<f64>("--month-target", hours)),
+				_ => specifier.value_of("target").map(|hours| exit_if_number_is_invalid::<f64>("--target", hours))
+			};
+			// Also rejects NaN, and anything that would round down to a zero second target.
+			if target_hours.is_some_and(|hours| hours.is_nan() || hours * 60.0 < 1.0) {
+				println!("The target must be a number of hours of at least one minute, e.g. 0.5.\nExiting.");
+				process::exit(1)
+			}
+			let duration_style = if specifier.is_present("long") {
+				DurationStyle::Long
+			}
+			else if specifier.is_present("decimal") {
+				DurationStyle::Decimal
+			}
+			else if specifier.is_present("with-days") {
+				DurationStyle::WithDays
+			}
+			else {
+				DurationStyle::Short
+			};
+			let options = SummaryOptions {
+				duration_style,
+				by_project: specifier.is_present("by-project"),
+				include_open_session: specifier.is_present("today"),
+				target_seconds: target_hours.map(|hours| (hours * 3600.0) as i64),
+				rounding: specifier.value_of("round").map(|minutes| exit_if_number_is_invalid::<i64>("--round", minutes)).
+					or(config.round_minutes).map(|minutes| Rounding {
+					increment_seconds: minutes * 60,
+					mode: match specifier.value_of("round-mode") {
+						Some("up") => RoundingMode::Up,
+						Some("down") => RoundingMode::Down,
+						_ => RoundingMode::Nearest
+					}
+				}),
+				rate: specifier.value_of("rate").map(|rate| exit_if_number_is_invalid::<f64>("--rate", rate)).
+					or(config.rate).map(|per_hour| Rate {
+					per_hour,
+					currency: String::from(specifier.value_of("currency").unwrap_or(&config.currency))
+				}),
+				project: specifier.value_of("project").map(String::from),
+				excluded_weekdays: if specifier.is_present("no-weekends") { weekend } else { vec![] },
+				summary_only: specifier.is_present("summary"),
+				plain: specifier.is_present("plain"),
+				tsv: specifier.is_present("tsv"),
+				hours_only: specifier.is_present("hours-only"),
+				fill_days: specifier.is_present("fill-days"),
+				show_idle: specifier.is_present("show-idle"),
+				total_label: String::from("Total"),
+				color
+			};
+			if let Some(Rounding { increment_seconds, .. }) = options.rounding {
+				if increment_seconds <= 0 {
+					println!("--round must be a positive number of minutes.\nExiting.");
+					process::exit(1)
+				}
+			}
 			if specifier.is_present("week") {
-				print_weekly_summary()
+				let week_start = specifier.value_of("week-start").and_then(parse_weekday).
+					or_else(|| parse_weekday(&config.week_start)).unwrap_or(chrono::Weekday::Mon);
+				print_weekly_summary(&journal, week_start, &options)
+			}
+			else if specifier.is_present("mtd") {
+				print_month_to_date_summary(&journal, &options)
+			}
+			else if specifier.is_present("ytd") {
+				let start_of_year = chrono::Local.ymd(chrono::Local::today().year(), 1, 1);
+				print_daily_durations_since(&journal, start_of_local_day(start_of_year), &options)
+			}
+			else if specifier.is_present("today") {
+				print_daily_durations_since(&journal, start_of_local_day(chrono::Local::today()), &options)
+			}
+			else if specifier.is_present("yesterday") {
+				let today = chrono::Local::today();
+				print_daily_durations_between(&journal, start_of_local_day(today.pred()), start_of_local_day(today), &options)
+			}
+			else if let Some(from) = specifier.value_of("from") {
+				let from_date = exit_if_date_is_invalid(from);
+				let end_time = match specifier.value_of("to").map(exit_if_date_is_invalid) {
+					Some(to_date) => start_of_local_day(to_date.succ()),
+					None => chrono::UTC::now()
+				};
+				if end_time <= start_of_local_day(from_date) {
+					println!("--from date {} is after --to date.\nExiting.", from_date.format("%F"));
+					process::exit(1)
+				}
+				print_daily_durations_between(&journal, start_of_local_day(from_date), end_time, &options)
+			}
+			else if let Some(days) = specifier.value_of("days") {
+				let days = exit_if_days_are_invalid(days);
+				print_daily_durations_since(&journal, start_of_local_day(start_of_days_ago(chrono::Local::today(), days)), &options)
+			}
+			else if let Some(month) = specifier.value_of("month") {
+				let (start_date, end_date) = exit_if_month_is_invalid(month);
+				let options = SummaryOptions {
+					total_label: format!("Total {}", start_date.format("%b %Y")),
+					..options
+				};
+				print_daily_durations_between(&journal, start_of_local_day(start_date), start_of_local_day(end_date), &options)
+			}
+			else {
+				let max_session_hours = specifier.value_of("max-session-hours").
+					map(|hours| exit_if_number_is_invalid::<f64>("--max-session-hours", hours)).
+					unwrap_or(DEFAULT_MAX_SESSION_HOURS);
+				print_current_state(&journal, &session_records, specifier.value_of("project"), max_session_hours, config.daily_goal_hours, duration_style);
+				if !specifier.is_present("state-only") && get_log_state(&journal) != LogState::Empty {
+					print_today_total(&journal, duration_style)
+				}
+			}
+		},
+		("status", Some(specifier)) => {
+			if specifier.is_present("short") {
+				print_short_status(&session_records, specifier.value_of("project"))
+			}
+			else {
+				let duration_style = if specifier.is_present("long") { DurationStyle::Long } else { DurationStyle::Short };
+				let max_session_hours = specifier.value_of("max-session-hours").
+					map(|hours| exit_if_number_is_invalid::<f64>("--max-session-hours", hours)).
+					unwrap_or(DEFAULT_MAX_SESSION_HOURS);
+				print_current_state(&journal, &session_records, specifier.value_of("project"), max_session_hours, config.daily_goal_hours, duration_style)
+			}
+		},
+		("doctor", Some(specifier)) => {
+			diagnose_log(&journal, specifier.is_present("fix"))
+		},
+		("repair", Some(specifier)) => {
+			repair_log(&journal, specifier.is_present("fix"))
+		},
+		("check", _) => {
+			check_log_sequence(&journal)
+		},
+		("stats", Some(specifier)) => {
+			let since = if specifier.is_present("week") {
+				Some(start_of_week(chrono::Local::today(), parse_weekday(&config.week_start).unwrap_or(chrono::Weekday::Mon)))
 			}
 			else if specifier.is_present("mtd") {
-				print_month_to_date_summary()
+				Some(start_of_month())
+			}
+			else if specifier.is_present("ytd") {
+				Some(chrono::Local.ymd(chrono::Local::today().year(), 1, 1))
+			}
+			else {
+				None
+			};
+			print_stats(&journal, since.map(start_of_local_day))
+		},
+		("list", Some(specifier)) => {
+			let tail = specifier.value_of("tail").map(|n| exit_if_number_is_invalid::<usize>("--tail", n));
+			print_records(&journal, tail)
+		},
+		("log", Some(specifier)) => {
+			print_log(&journal, exit_if_number_is_invalid::<usize>("-n", specifier.value_of("count").unwrap_or("10")), specifier.is_present("notes"))
+		},
+		("import", Some(specifier)) => {
+			import_sessions(&journal, specifier.value_of("file").unwrap_or(""), journal.is_dry_run())
+		},
+		("export", Some(specifier)) => {
+			let format = if specifier.is_present("csv") {
+				"csv-records"
+			}
+			else if specifier.is_present("csv-sessions") {
+				"csv-sessions"
+			}
+			else if specifier.is_present("ics") {
+				"ics"
+			}
+			else {
+				specifier.value_of("format").unwrap_or("csv")
+			};
+			if specifier.is_present("markdown") {
+				let start_date = if specifier.is_present("week") {
+					Some(start_of_week(chrono::Local::today(), parse_weekday(&config.week_start).unwrap_or(chrono::Weekday::Mon)))
+				}
+				else if specifier.is_present("mtd") {
+					Some(start_of_month())
+				}
+				else {
+					specifier.value_of("days").map(exit_if_days_are_invalid).
+						map(|days| start_of_days_ago(chrono::Local::today(), days))
+				};
+				let start_time = start_date.map_or(chrono::UTC.timestamp(0, 0), start_of_local_day);
+				let duration_style = if specifier.is_present("decimal") { DurationStyle::Decimal } else { DurationStyle::Short };
+				let export_result = match specifier.value_of("output") {
+					Some(path) => File::create(path).and_then(|mut f| write_markdown_table(&journal, start_time, duration_style, &mut f)),
+					None => write_markdown_table(&journal, start_time, duration_style, &mut io::stdout())
+				};
+				if let Err(e) = export_result {
+					println!("Couldn't export punch log: {}.\nExiting.", e);
+					process::exit(1)
+				}
+				return
+			}
+			let exporter = export::exporter_for(format).unwrap_or(export::sessions_as_csv);
+			let export_result = match specifier.value_of("output") {
+				Some(path) => File::create(path).and_then(|mut f| exporter(&journal, &mut f)),
+				None => exporter(&journal, &mut io::stdout())
+			};
+			if let Err(e) = export_result {
+				println!("Couldn't export punch log: {}.\nExiting.", e);
+				process::exit(1)
+			}
+		},
+		("in", Some(specifier)) => {
+			let previous_session = if args.is_present("verbose") { last_completed_session(&session_records) } else { None };
+			if specifier.is_present("force") {
+				force_punch(&journal, Action::PunchIn, chrono::UTC::now(), specifier.value_of("project").or(config.default_project.as_deref()), None);
+				return
+			}
+			exit_if_open_since_earlier_day(&session_records);
+			if specifier.is_present("resume") {
+				match journal.resume() {
+					Ok(punch_in) if journal.is_dry_run() =>
+						println!("Dry run, would resume session started at {}", punch_in.timestamp.with_timezone(&Local).format("%H:%M")),
+					Ok(punch_in) => println!("Resumed session started at {}", punch_in.timestamp.with_timezone(&Local).format("%H:%M")),
+					Err(e) => exit_if_punch_failed(Err(e))
+				}
+				return
+			}
+			else if specifier.is_present("carry-over") {
+				exit_if_punch_failed(journal.punch_in_carrying_over())
+			}
+			else {
+				punch_in(&journal, specifier.value_of("project").or(config.default_project.as_deref()))
+			}
+			if args.is_present("verbose") {
+				report_punch_in(&journal, previous_session)
+			}
+		},
+		("out", Some(specifier)) => {
+			let note = specifier.value_of("note-option").or_else(|| specifier.value_of("note"));
+			if specifier.is_present("if-open") && get_log_state(&journal) != LogState::OpenSession {
+				return
+			}
+			if let Some(project) = specifier.value_of("project") {
+				exit_if_punch_failed(journal.ensure_open_project_is(project))
+			}
+			if specifier.is_present("force") {
+				let timestamp = specifier.value_of("at").map_or_else(chrono::UTC::now, exit_if_punch_time_is_invalid);
+				force_punch(&journal, Action::PunchOut, timestamp, None, note)
+			}
+			else if specifier.is_present("eod") {
+				punch_out_at_end_of_day(&journal, &config.workday_end, note)
+			}
+			else if let Some(time) = specifier.value_of("at") {
+				exit_if_punch_failed(journal.punch_out_at(exit_if_punch_time_is_invalid(time), note))
+			}
+			else {
+				punch_out(&journal, note)
+			}
+			if args.is_present("verbose") {
+				report_punch_out(&journal)
+			}
+		},
+		("break", Some(specifier)) => {
+			match specifier.value_of("minutes").map(|minutes| exit_if_number_is_invalid::<i64>("--minutes", minutes)) {
+				Some(minutes) if minutes <= 0 => {
+					println!("--minutes must be a positive number.\nExiting.");
+					process::exit(1)
+				},
+				Some(minutes) => exit_if_punch_failed(journal.record_break(chrono::Duration::minutes(minutes))),
+				None => exit_if_punch_failed(journal.start_break())
+			}
+		},
+		("resume", _) => {
+			exit_if_punch_failed(journal.end_break())
+		},
+		("amend", Some(specifier)) => {
+			if let Some(time) = specifier.value_of("time") {
+				amend_last_record(&journal, time)
+			}
+			if let Some(action) = specifier.value_of("action") {
+				let offset = specifier.value_of("offset").map_or(0, |n| exit_if_number_is_invalid::<usize>("--offset", n));
+				amend_record_action(&journal, offset, action)
+			}
+		},
+		// Under dry-run, the line that would have been appended is all there is to report.
+		("toggle", _) => {
+			if get_log_state(&journal) == LogState::OpenSession {
+				punch_out(&journal, None);
+				if !journal.is_dry_run() {
+					println!("Punched out")
+				}
 			}
 			else {
-				print_current_state()
+				punch_in(&journal, config.default_project.as_deref());
+				if !journal.is_dry_run() {
+					println!("Punched in")
+				}
 			}
 		},
-		("in", _) => {
-			ensure_last_record_is_of_action(Action::PunchOut);
-			write_record_to_log(chrono::UTC::now(), Action::PunchIn);								
+		("watch", _) => {
+			watch_open_session(&journal)
+		},
+		("completions", Some(specifier)) => {
+			let shell = specifier.value_of("shell").and_then(|shell| shell.parse::<Shell>().ok()).unwrap_or(Shell::Bash);
+			build_cli().gen_completions_to("punch", shell, &mut io::stdout())
 		},
-		("out", _) => {
-			ensure_last_record_is_of_action(Action::PunchIn);
-			write_record_to_log(chrono::UTC::now(), Action::PunchOut);
+		("rm", Some(specifier)) => {
+			let before = exit_if_date_is_invalid(specifier.value_of("before").unwrap_or(""));
+			match journal.remove_records_before(start_of_local_day(before)) {
+				Ok(count) if journal.is_dry_run() => println!("Dry run, would remove {} records before {}", count, before.format("%F")),
+				Ok(count) => println!("Removed {} records before {}", count, before.format("%F")),
+				Err(e) => {
+					println!("Couldn't remove records before {}: {}.\nExiting.", before.format("%F"), e);
+					process::exit(1)
+				}
+			}
+		},
+		("cancel", _) => {
+			match journal.cancel_open_session() {
+				Ok(Some(record)) if journal.is_dry_run() =>
+					println!("Dry run, would cancel open session from {}", record.timestamp.with_timezone(&Local).format("%H:%M")),
+				Ok(Some(record)) => println!("Cancelled open session from {}", record.timestamp.with_timezone(&Local).format("%H:%M")),
+				Ok(None) => println!("Nothing to cancel, the last record isn't a punch-in"),
+				Err(e) => {
+					println!("Couldn't update punch log: {}.\nExiting.", e);
+					process::exit(1)
+				}
+			}
 		},
 		_ => {
 			println!("Unknown command")				
@@ -84,24 +505,299 @@ fn main() {
 	}
 }
 
-fn write_record_to_log(tm: DateTime<UTC>, action: Action) {
-	let action_token = match action {
-		Action::PunchIn => "I",
-		Action::PunchOut => "O",
-		Action::Unset => "U"
+fn build_cli() -> App<'static, 'static> {
+    App::new("Punch").
+	    about("A simple time tracker app").
+	    version("0.1").
+        setting(AppSettings::ArgRequiredElseHelp).
+        arg(Arg::with_name("timezone").long("timezone").takes_value(true).global(true).
+        	possible_values(&["local", "utc"]).help("Display times in local time or UTC")).
+        arg(Arg::with_name("auto-out").long("auto-out").global(true).
+        	help("Punch out of a session left open longer than idle_hours without asking")).
+        arg(Arg::with_name("dry-run").long("dry-run").global(true).
+        	help("Check and print what would be written to the log without changing it")).
+        arg(Arg::with_name("verbose").long("verbose").short("v").global(true).
+        	help("Report the time punched in or out and how long the session lasted")).
+        arg(Arg::with_name("color").long("color").takes_value(true).value_name("WHEN").global(true).
+        	possible_values(&["always", "never", "auto"]).help("When to colorize output (default auto)")).
+        arg(Arg::with_name("no-color").long("no-color").global(true).conflicts_with("color").help("Same as --color never")).
+        subcommand(SubCommand::with_name("config").about("Display or change configuration").
+	        arg(Arg::with_name("show").long("show").help("Print the effective settings")).
+	        subcommand(SubCommand::with_name("show").about("Print the effective settings")).
+	        subcommand(SubCommand::with_name("set").about("Write a setting to the config file").
+	        	arg(Arg::with_name("key").index(1).required(true).possible_values(&config::KEYS).help("The setting to change")).
+	        	arg(Arg::with_name("value").index(2).required(true).help("Its new value")))).
+        subcommand(SubCommand::with_name("in").about("Punch in").
+	        arg(Arg::with_name("project").long("project").short("p").takes_value(true).value_name("NAME").
+	        	help("Attribute the session to a project (default default_project from config)")).
+	        arg(Arg::with_name("resume").long("resume").conflicts_with("project").
+	        	help("Undo the last punch-out and carry on with that session")).
+	        arg(Arg::with_name("carry-over").long("carry-over").conflicts_with_all(&["project", "resume"]).
+	        	help("Start a new session with the project and note of the last one")).
+	        arg(Arg::with_name("force").long("force").conflicts_with_all(&["resume", "carry-over"]).
+	        	help("Punch in even if already punched in, e.g. to repair the log by hand"))).
+        subcommand(SubCommand::with_name("out").about("Punch out").
+	        arg(Arg::with_name("note").index(1).help("A note describing what you worked on")).
+	        arg(Arg::with_name("note-option").long("note").takes_value(true).value_name("TEXT").conflicts_with("note").
+	        	help("Same as the positional note")).
+	        arg(Arg::with_name("eod").long("eod").
+	        	help("Punch out at workday_end (default 17:00) on the day you punched in")).
+	        arg(Arg::with_name("at").long("at").takes_value(true).value_name("TIME").conflicts_with("eod").
+	        	help("Punch out at TIME, either HH:MM today or YYYY-MM-DD HH:MM")).
+	        arg(Arg::with_name("project").long("project").short("p").takes_value(true).value_name("NAME").
+	        	help("Only punch out if the open session is for this project")).
+	        arg(Arg::with_name("if-open").long("if-open").
+	        	help("Do nothing, successfully, when already punched out, e.g. for cron")).
+	        arg(Arg::with_name("force").long("force").conflicts_with_all(&["eod", "project", "if-open"]).
+	        	help("Punch out even if not punched in, e.g. to repair the log by hand"))).
+        subcommand(SubCommand::with_name("amend").about("Change the time or action of a record").
+	        arg(Arg::with_name("time").index(1).value_name("HH:MM").
+	        	help("The new local time of the last record, on the same day as it")).
+	        arg(Arg::with_name("action").long("action").takes_value(true).possible_values(&["in", "out", "break", "resume"]).
+	        	help("The new action of the record")).
+	        arg(Arg::with_name("offset").long("offset").takes_value(true).value_name("N").requires("action").conflicts_with("time").
+	        	help("With --action, change the Nth record from the end instead of the last one (0)")).
+	        group(ArgGroup::with_name("change").args(&["time", "action"]).multiple(true).required(true))).
+        subcommand(SubCommand::with_name("toggle").about("Punch in if punched out, otherwise punch out")).
+        subcommand(SubCommand::with_name("watch").about("Show the open session's time worked until you punch out")).
+        subcommand(SubCommand::with_name("cancel").about("Discard the open session, if nothing was recorded after punching in")).
+        subcommand(SubCommand::with_name("rm").about("Remove old records from the log").
+	        arg(Arg::with_name("before").long("before").takes_value(true).value_name("DATE").required(true).
+	        	help("Remove records before this YYYY-MM-DD date, keeping those at or after it"))).
+        subcommand(SubCommand::with_name("break").about("Start a break, which doesn't count towards time worked").
+	        arg(Arg::with_name("minutes").long("minutes").takes_value(true).value_name("MINUTES").
+	        	help("Record a break of MINUTES that has just ended instead, e.g. after lunch"))).
+        subcommand(SubCommand::with_name("resume").about("End a break")).
+        subcommand(SubCommand::with_name("status").about("Display whether you are punched in").
+	        arg(Arg::with_name("short").long("short").short("s").help("Print only IN <duration> or OUT, e.g. for a shell prompt")).
+	        arg(Arg::with_name("project").long("project").short("p").takes_value(true).value_name("NAME").
+	        	help("Only count as punched in when the open session is for this project")).
+	        arg(Arg::with_name("long").long("long").short("l").conflicts_with("short").help("Spell out durations, e.g. 8 hours 30 minutes")).
+	        arg(Arg::with_name("max-session-hours").long("max-session-hours").takes_value(true).value_name("HOURS").
+	        	conflicts_with("short").help("Warn when the open session is longer than HOURS (default 16)"))).
+        subcommand(SubCommand::with_name("card").about("Display state").
+	        arg(Arg::with_name("week").long("week").short("w").help("Display summary for the last week")).
+	        arg(Arg::with_name("week-start").long("week-start").takes_value(true).value_name("DAY").
+	        	possible_values(&WEEKDAY_NAMES).help("Day the week starts on for --week (default mon)")).
+	        arg(Arg::with_name("mtd").long("mtd").short("m").help("Display summary for the month to date")).
+	        arg(Arg::with_name("ytd").long("ytd").help("Display summary for the year to date")).
+	        arg(Arg::with_name("today").long("today").short("t").help("Display summary for today, including an open session")).
+	        arg(Arg::with_name("yesterday").long("yesterday").short("y").help("Display summary for yesterday")).
+	        arg(Arg::with_name("from").long("from").takes_value(true).value_name("DATE").
+	        	help("Display summary starting on DATE (YYYY-MM-DD)")).
+	        arg(Arg::with_name("to").long("to").takes_value(true).value_name("DATE").requires("from").
+	        	help("Display summary up to and including DATE (YYYY-MM-DD), defaults to now")).
+	        arg(Arg::with_name("month").long("month").takes_value(true).value_name("YYYY-MM").
+	        	help("Display summary for a whole calendar month")).
+	        arg(Arg::with_name("days").long("days").takes_value(true).value_name("N").
+	        	help("Display summary since midnight N days ago")).
+	        group(ArgGroup::with_name("range").args(&["week", "mtd", "ytd", "today", "yesterday", "from", "month", "days"]).conflicts_with("state-only")).
+	        arg(Arg::with_name("summary").long("summary").help("Only print the totals, without the per-day lines")).
+	        arg(Arg::with_name("plain").long("plain").help("Print one `date: duration` line per day instead of a table")).
+	        arg(Arg::with_name("tsv").long("tsv").requires("range").conflicts_with("hours-only").
+	        	help("Only print one `date<TAB>seconds` line per day, for scripts")).
+	        arg(Arg::with_name("hours-only").long("hours-only").requires("range").
+	        	help("Only print the total as decimal hours, e.g. 6.5")).
+	        arg(Arg::with_name("no-weekends").long("no-weekends").
+	        	help("Leave out days in the weekend config setting (default sat and sun)")).
+	        arg(Arg::with_name("fill-days").long("fill-days").requires("range").help("Also list days with no recorded time")).
+	        arg(Arg::with_name("show-idle").long("show-idle").requires("range").
+	        	help("Add an Idle column with the time between the first punch-in and last punch-out that wasn't worked")).
+	        arg(Arg::with_name("max-session-hours").long("max-session-hours").takes_value(true).value_name("HOURS").
+	        	help("Warn when the open session is longer than HOURS (default 16)")).
+	        arg(Arg::with_name("state-only").long("state-only").
+	        	help("Only show whether you are punched in, without today's total")).
+	        arg(Arg::with_name("long").long("long").short("l").help("Spell out durations, e.g. 8 hours 30 minutes")).
+	        arg(Arg::with_name("decimal").long("decimal").conflicts_with("long").help("Show durations as decimal hours, e.g. 8.25")).
+	        arg(Arg::with_name("with-days").long("with-days").conflicts_with_all(&["long", "decimal"]).
+	        	help("Show durations of a day or more with days, e.g. 1d 04h30m")).
+	        arg(Arg::with_name("project").long("project").short("p").takes_value(true).value_name("NAME").
+	        	help("Only count sessions for this project")).
+	        arg(Arg::with_name("by-project").long("by-project").help("Break the summary total down by project")).
+	        arg(Arg::with_name("target").long("target").takes_value(true).value_name("HOURS").requires("range").
+	        	help("Compare the total against a target number of hours, e.g. 40 with --week")).
+	        arg(Arg::with_name("month-target").long("month-target").takes_value(true).value_name("HOURS").requires("mtd").
+	        	help("Compare the month to date total against a target number of hours")).
+	        arg(Arg::with_name("round").long("round").takes_value(true).value_name("MINUTES").
+	        	help("Round each session (not each day) to a multiple of MINUTES before summing")).
+	        arg(Arg::with_name("round-mode").long("round-mode").takes_value(true).
+	        	possible_values(&["nearest", "up", "down"]).help("How to round with --round (default nearest)")).
+	        arg(Arg::with_name("rate").long("rate").takes_value(true).value_name("AMOUNT").
+	        	help("Print earnings for the total at AMOUNT per hour")).
+	        arg(Arg::with_name("currency").long("currency").takes_value(true).value_name("CODE").
+	        	help("Currency of --rate, e.g. USD or EUR (default USD)"))).
+        subcommand(SubCommand::with_name("doctor").about("Check the log for out of order, duplicate or unreadable records").
+	        arg(Arg::with_name("fix").long("fix").help("Sort records by timestamp and rewrite the log"))).
+        subcommand(SubCommand::with_name("repair").about("Find unreadable, truncated or out of order records").
+	        arg(Arg::with_name("fix").long("fix").help("Back the log up to punch.log.bak and drop the bad records"))).
+        subcommand(SubCommand::with_name("check").about("Check that punches and breaks come in a valid order, exiting non-zero if not")).
+        subcommand(SubCommand::with_name("stats").about("Display totals over the whole log").
+	        arg(Arg::with_name("week").long("week").short("w").help("Only count sessions from the week to date")).
+	        arg(Arg::with_name("mtd").long("mtd").short("m").help("Only count sessions from the month to date")).
+	        arg(Arg::with_name("ytd").long("ytd").help("Only count sessions from the year to date")).
+	        group(ArgGroup::with_name("range").args(&["week", "mtd", "ytd"]))).
+        subcommand(SubCommand::with_name("list").about("List recorded punches").
+	        arg(Arg::with_name("tail").long("tail").short("t").takes_value(true).value_name("N").
+	        	help("Only list the last N records"))).
+        subcommand(SubCommand::with_name("log").about("Show the most recent records with their offset from the end").
+	        arg(Arg::with_name("count").short("n").takes_value(true).value_name("N").default_value("10").
+	        	help("Number of records to show")).
+	        arg(Arg::with_name("notes").long("notes").help("Only show punch-outs that carry a note"))).
+        subcommand(SubCommand::with_name("import").about("Add sessions from a JSON array of {\"start\", \"end\"} objects").
+	        arg(Arg::with_name("file").index(1).required(true).value_name("FILE").help("The JSON file to import"))).
+        subcommand(SubCommand::with_name("export").about("Export completed sessions").
+	        arg(Arg::with_name("format").long("format").short("f").takes_value(true).
+	        	possible_values(&export::FORMATS).default_value("csv").help("Output format")).
+	        arg(Arg::with_name("output").long("output").short("o").takes_value(true).
+	        	value_name("FILE").help("Write to FILE instead of stdout")).
+	        arg(Arg::with_name("csv").long("csv").conflicts_with("csv-sessions").
+	        	help("Export every record as timestamp,action rows")).
+	        arg(Arg::with_name("csv-sessions").long("csv-sessions").conflicts_with("ics").
+	        	help("Export completed sessions as start,end,duration_seconds rows")).
+	        arg(Arg::with_name("ics").long("ics").conflicts_with("csv").
+	        	help("Export completed sessions as iCalendar events")).
+	        arg(Arg::with_name("markdown").long("markdown").conflicts_with_all(&["csv", "csv-sessions", "ics"]).
+	        	help("Export hours per day as a Markdown table")).
+	        arg(Arg::with_name("week").long("week").short("w").requires("markdown").help("With --markdown, only the week to date")).
+	        arg(Arg::with_name("mtd").long("mtd").short("m").requires("markdown").help("With --markdown, only the month to date")).
+	        arg(Arg::with_name("days").long("days").takes_value(true).value_name("N").requires("markdown").
+	        	help("With --markdown, only the days since midnight N days ago")).
+	        group(ArgGroup::with_name("range").args(&["week", "mtd", "days"])).
+	        arg(Arg::with_name("decimal").long("decimal").requires("markdown").help("With --markdown, show decimal hours, e.g. 8.25"))).
+        subcommand(SubCommand::with_name("completions").about("Print a shell completion script").setting(AppSettings::Hidden).
+	        arg(Arg::with_name("shell").index(1).required(true).possible_values(&Shell::variants()).help("The shell to complete in")))
+}
+
+fn punch_in(journal: &Journal, project: Option<&str>) {
+	exit_if_punch_failed(journal.punch_in(project))
+}
+
+fn punch_out(journal: &Journal, note: Option<&str>) {
+	exit_if_punch_failed(journal.punch_out(note))
+}
+
+fn force_punch(journal: &Journal, action: Action, timestamp: chrono::DateTime<UTC>, project: Option<&str>, note: Option<&str>) {
+	let record = Record {
+		timestamp,
+		action,
+		project: project.map(String::from),
+		note: note.map(String::from)
+	};
+	match journal.append_ignoring_state(&record) {
+		Ok(Some(overridden)) => println!("Warning: recorded anyway because of --force, overriding \"{}\"", overridden),
+		Ok(None) => {},
+		Err(e) => {
+			println!("Couldn't update punch log: {}.\nExiting.", e);
+			process::exit(1)
+		}
+	}
+}
+
+fn punch_out_at_end_of_day(journal: &Journal, workday_end: &str, note: Option<&str>) {
+	let end_of_day = match chrono::NaiveTime::parse_from_str(workday_end, "%H:%M") {
+		Ok(end_of_day) => end_of_day,
+		Err(_) => {
+			println!("Invalid workday_end '{}' in config, expected HH:MM.\nExiting.", workday_end);
+			process::exit(1)
+		}
+	};
+	let open_session = match journal.last_session_records().map(|session_records| session::pair_sessions(&session_records).pop()) {
+		Ok(Some(session)) if session.end.is_none() => session,
+		Ok(_) => return exit_if_punch_failed(Err(PunchError::AlreadyPunchedOut)),
+		Err(e) => {
+			println!("Couldn't read entry: {}.\nExiting.", e);
+			process::exit(1)
+		}
 	};
 	
-    let mut config_file = journal::get_conf_file(false, true).unwrap();
-    let fmt = tm.format("%FT%T");
-	let formatted_timestamp = fmt.to_string();
-	journal::append_to_file(format!("{}_{}\n", formatted_timestamp, action_token).as_bytes(), &mut config_file);
+	let punch_in_date = open_session.date();
+	if punch_in_date != chrono::Local::today() {
+		println!("Punched in on {}, not today; use `punch out --at` with a date instead.\nExiting.", punch_in_date.format("%F"));
+		process::exit(1)
+	}
+	let punch_out_time = match punch_in_date.and_time(end_of_day) {
+		Some(punch_out_time) => punch_out_time.with_timezone(&UTC),
+		None => {
+			println!("{} doesn't exist on {}.\nExiting.", workday_end, punch_in_date.format("%F"));
+			process::exit(1)
+		}
+	};
+	if punch_out_time > chrono::UTC::now() {
+		println!("It isn't {} yet.\nExiting.", workday_end);
+		process::exit(1)
+	}
+	exit_if_punch_failed(journal.punch_out_at(punch_out_time, note))
+}
+
+fn read_last_session_records(journal: &Journal) -> Vec<Record> {
+	match journal.last_session_records() {
+		Ok(session_records) => session_records,
+		Err(e) => {
+			println!("Couldn't read entry: {}.\nExiting.", e);
+			process::exit(1)
+		}
+	}
+}
+
+fn last_completed_session(session_records: &[Record]) -> Option<Session> {
+	session::pair_sessions(session_records).pop().filter(|session| session.end.is_some())
+}
+
+fn report_punch_in(journal: &Journal, previous_session: Option<Session>) {
+	let punched_in_at = match journal.last_record() {
+		Ok(Some(record)) => record.timestamp,
+		_ => return
+	};
+	let mut report = format!("Punched in at {}.", punched_in_at.with_timezone(&Local).format("%H:%M"));
+	if let Some(previous_session) = previous_session {
+		let end = previous_session.end.unwrap_or(punched_in_at);
+		report.push_str(&format!(" Previous session: {}, ended {} ago.", format_duration(previous_session.duration_until(end)),
+			format_duration(punched_in_at.sub(end))));
+	}
+	println!("{}", report)
+}
+
+fn report_punch_out(journal: &Journal) {
+	if let Some(session) = last_completed_session(&read_last_session_records(journal)) {
+		let end = session.end.unwrap_or(session.start);
+		println!("Punched out at {}. Session: {}.", end.with_timezone(&Local).format("%H:%M"), format_duration(session.duration_until(end)))
+	}
 }
 
-fn print_month_to_date_summary() {
-	let mut start_of_month = chrono::UTC::now().with_second(0).
-		map(|ts| ts.with_minute(0).
-			map(|ts| ts.with_hour(0))
-		).unwrap().unwrap().unwrap();
+// A session left open from an earlier day would otherwise run through the night, so point at
+// `punch out --at` rather than just saying it's already open.
+fn exit_if_open_since_earlier_day(session_records: &[Record]) {
+	if let Some(session) = session::pair_sessions(session_records).pop().filter(|session| session.end.is_none()) {
+		let start = session.start.with_timezone(&Local);
+		if start.date() < chrono::Local::today() {
+			println!("Still punched in since {}, punch out first with `punch out --at \"{} HH:MM\"`",
+				start.format("%a %F %H:%M"), start.format("%F"));
+			process::exit(GUARD_FAILURE_EXIT_CODE)
+		}
+	}
+}
+
+fn exit_if_punch_failed(punch_result: Result<(), PunchError>) {
+	match punch_result {
+		Ok(_) => {},
+		Err(e @ PunchError::AlreadyPunchedIn) | Err(e @ PunchError::AlreadyPunchedOut) |
+		Err(e @ PunchError::AlreadyOnBreak) | Err(e @ PunchError::NotOnBreak) | Err(e @ PunchError::OtherProjectOpen(_)) => {
+			println!("{}", e);
+			process::exit(GUARD_FAILURE_EXIT_CODE)
+		},
+		Err(e) => {
+			println!("Couldn't update punch log: {}.\nExiting.", e);
+			process::exit(1)
+		}
+	}
+}
+
+fn print_month_to_date_summary(journal: &Journal, options: &SummaryOptions) {
+	print_daily_durations_since(journal, start_of_local_day(start_of_month()), options);
+}
+
+fn start_of_month() -> chrono::Date<Local> {
+	let mut start_of_month = chrono::Local::today();
 		
 	loop {
 		if start_of_month.day() == 1 {
@@ -109,220 +805,1246 @@ fn print_month_to_date_summary() {
 		}
 		start_of_month = start_of_month.sub(chrono::Duration::days(1));
 	}
-	
-	print_daily_durations_since(start_of_month);
+	start_of_month
+}
+
+fn print_weekly_summary(journal: &Journal, week_start: chrono::Weekday, options: &SummaryOptions) {
+	print_daily_durations_since(journal, start_of_local_day(start_of_week(chrono::Local::today(), week_start)), options);
 }
 
-fn print_weekly_summary() {
-	let mut start_of_week = chrono::UTC::now().with_second(0).
-		map(|ts| ts.with_minute(0).
-			map(|ts| ts.with_hour(0))
-		).unwrap().unwrap().unwrap();
+fn start_of_week(today: chrono::Date<Local>, week_start: chrono::Weekday) -> chrono::Date<Local> {
+	let mut start_of_week = today;
 		
 	loop {
-		if start_of_week.weekday() == chrono::Weekday::Mon {
+		if start_of_week.weekday() == week_start {
 			break
 		}
 		start_of_week = start_of_week.sub(chrono::Duration::days(1));
 	}
+	start_of_week
+}
+
+// Where a `--days` range starts, so `--days 7` also covers the same weekday a week ago.
+fn start_of_days_ago(today: chrono::Date<Local>, days: i64) -> chrono::Date<Local> {
+	today.sub(chrono::Duration::days(days))
+}
+
+// Returns whether any records were added.
+fn split_open_session_at_midnight(journal: &Journal) -> bool {
+	match journal.split_open_session_at_midnight() {
+		Ok(ref added) if added.is_empty() || journal.is_dry_run() => false,
+		Ok(added) => {
+			// On stderr like the idle notices, so that `status --short` prints nothing else.
+			eprintln!("Split the open session at midnight, adding {} records", added.len());
+			true
+		},
+		Err(e) => {
+			println!("Couldn't split the open session at midnight: {}.\nExiting.", e);
+			process::exit(1)
+		}
+	}
+}
+
+// A session open for longer than `idle_hours` was probably left open by mistake. It's closed
+// `idle_hours` after it started, either straight away with `--auto-out` or after asking.
+// Returns whether the session was closed.
+fn close_idle_session(journal: &Journal, session_records: &[Record], idle_hours: f64, auto_out: bool) -> bool {
+	let (last_record, open_session) = match (session_records.last(), session::pair_sessions(session_records).pop()) {
+		(Some(last_record), Some(session)) if session.end.is_none() => (last_record, session),
+		_ => return false
+	};
+	let idle_limit = chrono::Duration::seconds((idle_hours * 3600.0) as i64);
+	if chrono::UTC::now().sub(open_session.start) <= idle_limit {
+		return false
+	}
 	
-	print_daily_durations_since(start_of_week);
+	let punch_out_time = cmp::max(open_session.start.add(idle_limit), last_record.timestamp);
+	if !auto_out {
+		// Only ask when someone is there to answer and the question can't end up in piped output.
+		if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+			eprintln!("Punched in for more than {} hours since {}, pass --auto-out to punch out at {}",
+				idle_hours, open_session.start.with_timezone(&Local), punch_out_time.with_timezone(&Local));
+			return false
+		}
+		eprint!("Punched in for more than {} hours since {}. Punch out at {}? [y/N] ", idle_hours,
+			open_session.start.with_timezone(&Local), punch_out_time.with_timezone(&Local));
+		let mut answer = String::new();
+		let read_result = io::stderr().flush().and_then(|_| io::stdin().read_line(&mut answer));
+		if read_result.is_err() || !["y", "yes"].contains(&answer.trim().to_lowercase().as_str()) {
+			return false
+		}
+	}
+	exit_if_punch_failed(journal.punch_out_at(punch_out_time, None));
+	if journal.is_dry_run() {
+		return false
+	}
+	eprintln!("Punched out at {} for the session started at {}", punch_out_time.with_timezone(&Local),
+		open_session.start.with_timezone(&Local));
+	true
 }
 
-fn print_daily_durations_since(start_time: chrono::DateTime<UTC>) {
-	let mut daily_durations: Vec<DailyDuration> = vec![];
-	let mut record_offset = 0;
-	let mut record = empty_record();
-	let mut config_file = journal::get_conf_file(true, false).unwrap();
-	let mut current_date: chrono::Date<UTC> = 
-		chrono::UTC::now().date().add(chrono::Duration::days(1));
-		
-	let mut day_count: i64 = 0;
-	let mut total_seconds_in_current_day: i64 = 0;
+fn amend_last_record(journal: &Journal, time: &str) {
+	let new_time = match chrono::NaiveTime::parse_from_str(time, "%H:%M") {
+		Ok(new_time) => new_time,
+		Err(_) => {
+			println!("Invalid time '{}', expected HH:MM.\nExiting.", time);
+			process::exit(1)
+		}
+	};
+	let last_date = match journal.last_record() {
+		Ok(Some(record)) => record.timestamp.with_timezone(&Local).date(),
+		Ok(None) => {
+			println!("No punches yet, nothing to amend.\nExiting.");
+			process::exit(1)
+		},
+		Err(e) => {
+			println!("Couldn't read entry: {}.\nExiting.", e);
+			process::exit(1)
+		}
+	};
+	let timestamp = match last_date.and_time(new_time) {
+		Some(timestamp) => timestamp.with_timezone(&UTC),
+		None => {
+			println!("{} doesn't exist on {}.\nExiting.", time, last_date.format("%F"));
+			process::exit(1)
+		}
+	};
+	
+	match journal.amend_last_timestamp(timestamp) {
+		Ok(record) if journal.is_dry_run() => println!("Dry run, would amend to {}", describe_record(&record)),
+		Ok(record) => println!("Amended to {}", describe_record(&record)),
+		Err(e) => {
+			println!("Couldn't amend punch log: {}.\nExiting.", e);
+			process::exit(1)
+		}
+	}
+}
+
+fn amend_record_action(journal: &Journal, offset: usize, action: &str) {
+	let action = match action {
+		"in" => Action::PunchIn,
+		"out" => Action::PunchOut,
+		"break" => Action::BreakStart,
+		_ => Action::BreakEnd
+	};
+	match journal.amend_action(offset, action) {
+		Ok(record) if journal.is_dry_run() => println!("Dry run, would amend to {}", describe_record(&record)),
+		Ok(record) => println!("Amended to {}", describe_record(&record)),
+		Err(e) => {
+			println!("Couldn't amend punch log: {}.\nExiting.", e);
+			process::exit(1)
+		}
+	}
+}
+
+fn import_sessions(journal: &Journal, path: &str, dry_run: bool) {
+	let mut contents = String::new();
+	if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut contents)) {
+		println!("Couldn't read {}: {}.\nExiting.", path, e);
+		process::exit(1)
+	}
+	let sessions = match import::parse_sessions_json(&contents) {
+		Ok(sessions) => sessions,
+		Err(e) => {
+			println!("Couldn't parse {}: {}.\nExiting.", path, e);
+			process::exit(1)
+		}
+	};
+	match import::import_sessions(journal, &sessions, dry_run) {
+		Ok(records) => {
+			if dry_run {
+				for record in &records {
+					println!("{}", describe_record(record))
+				}
+				println!("Would add {} record(s)", records.len())
+			}
+			else {
+				println!("Added {} record(s)", records.len())
+			}
+		},
+		Err(e) => {
+			println!("Couldn't import {}: {}.\nExiting.", path, e);
+			process::exit(1)
+		}
+	}
+}
+
+fn print_config(config: &Config) {
+	println!("idle_hours = {}", config.idle_hours);
+	match config.round_minutes {
+		Some(minutes) => println!("round_minutes = {}", minutes),
+		None => println!("# round_minutes is not set")
+	}
+	println!("timezone = \"{}\"", config.timezone);
+	println!("week_start = \"{}\"", config.week_start);
+	println!("weekend = [{}]", config.weekend.iter().map(|day| format!("\"{}\"", day)).collect::<Vec<String>>().join(", "));
+	println!("workday_end = \"{}\"", config.workday_end);
+	match config.default_project {
+		Some(ref project) => println!("default_project = \"{}\"", project),
+		None => println!("# default_project is not set")
+	}
+	match config.daily_goal_hours {
+		Some(hours) => println!("daily_goal_hours = {}", hours),
+		None => println!("# daily_goal_hours is not set")
+	}
+	match config.rate {
+		Some(rate) => println!("rate = {}", rate),
+		None => println!("# rate is not set")
+	}
+	println!("currency = \"{}\"", config.currency);
+	println!("split_at_midnight = {}", config.split_at_midnight);
+}
+
+fn parse_weekday(name: &str) -> Option<chrono::Weekday> {
+	match name {
+		"mon" => Some(chrono::Weekday::Mon),
+		"tue" => Some(chrono::Weekday::Tue),
+		"wed" => Some(chrono::Weekday::Wed),
+		"thu" => Some(chrono::Weekday::Thu),
+		"fri" => Some(chrono::Weekday::Fri),
+		"sat" => Some(chrono::Weekday::Sat),
+		"sun" => Some(chrono::Weekday::Sun),
+		_ => None
+	}
+}
+
+fn exit_if_number_is_invalid<T: FromStr>(name: &str, value: &str) -> T where T::Err: fmt::Display {
+	match value.parse::<T>() {
+		Ok(number) => number,
+		Err(e) => {
+			println!("Invalid value for {}: {}.\nExiting.", name, e);
+			process::exit(1)
+		}
+	}
+}
+
+fn exit_if_date_is_invalid(value: &str) -> chrono::Date<Local> {
+	let local_date = chrono::NaiveDate::parse_from_str(value, "%F").ok().
+		and_then(|date| chrono::Local.from_local_date(&date).single());
+	match local_date {
+		Some(date) => date,
+		None => {
+			println!("Invalid date '{}', expected YYYY-MM-DD.\nExiting.", value);
+			process::exit(1)
+		}
+	}
+}
+
+fn exit_if_days_are_invalid(value: &str) -> i64 {
+	let days = exit_if_number_is_invalid::<i64>("--days", value);
+	if days <= 0 {
+		println!("--days must be a positive number.\nExiting.");
+		process::exit(1)
+	}
+	days
+}
+
+// Returns the first day of the month and the first day of the month after it.
+fn exit_if_month_is_invalid(value: &str) -> (chrono::Date<Local>, chrono::Date<Local>) {
+	let first_day = |year: i32, month: u32| chrono::NaiveDate::from_ymd_opt(year, month, 1).
+		and_then(|date| chrono::Local.from_local_date(&date).single());
+	let parts: Vec<&str> = value.split('-').collect();
+	let year_and_month = match parts.as_slice() {
+		[year, month] if year.len() == 4 && month.len() == 2 && value.bytes().filter(u8::is_ascii_digit).count() == 6 =>
+			year.parse::<i32>().ok().zip(month.parse::<u32>().ok()),
+		_ => None
+	};
+	let month_range = year_and_month.and_then(|(year, month)| {
+		let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+		first_day(year, month).zip(first_day(next_year, next_month))
+	});
+	match month_range {
+		Some(month_range) => month_range,
+		None => {
+			println!("Invalid month '{}', expected YYYY-MM.\nExiting.", value);
+			process::exit(1)
+		}
+	}
+}
+
+// Times are local, either HH:MM for today or a full YYYY-MM-DD HH:MM.
+fn exit_if_punch_time_is_invalid(value: &str) -> DateTime<UTC> {
+	let local_time = chrono::NaiveTime::parse_from_str(value, "%H:%M").ok().
+		map(|time| chrono::Local::today().naive_local().and_time(time)).
+		or_else(|| chrono::NaiveDateTime::parse_from_str(value, "%F %H:%M").ok()).
+		or_else(|| chrono::NaiveDateTime::parse_from_str(value, "%FT%H:%M").ok()).
+		and_then(|datetime| chrono::Local.from_local_datetime(&datetime).earliest());
+	match local_time {
+		Some(timestamp) => timestamp.with_timezone(&UTC),
+		None => {
+			println!("Invalid time '{}', expected HH:MM or YYYY-MM-DD HH:MM.\nExiting.", value);
+			process::exit(1)
+		}
+	}
+}
+
+fn start_of_local_day(date: chrono::Date<Local>) -> DateTime<UTC> {
+	date.and_hms(0, 0, 0).with_timezone(&UTC)
+}
+
+fn print_daily_durations_since(journal: &Journal, start_time: chrono::DateTime<UTC>, options: &SummaryOptions) {
+	print_daily_durations_between(journal, start_time, chrono::UTC::now(), options)
+}
+
+fn print_daily_durations_between(journal: &Journal, start_time: chrono::DateTime<UTC>, end_time: chrono::DateTime<UTC>, options: &SummaryOptions) {
+	let mut project_totals: BTreeMap<String, i64> = BTreeMap::new();
 	let mut total_seconds_in_time_range: i64 = 0;
 	
-	// TODO need to account for duration between now and last punch-in 
-	if get_last_record_action() == Action::PunchIn {
-		record_offset = 1
+	let (sessions, mut anomaly_count) = match journal.sessions_and_anomalies_between(start_time, end_time) {
+		Ok(sessions_and_anomalies) => sessions_and_anomalies,
+		Err(e) => {
+			println!("Couldn't read entry: {}.\nExiting.", e);
+			process::exit(1)
+		}
+	};
+	
+	let open_session_end = cmp::min(chrono::UTC::now(), end_time);
+	let mut session_durations = vec![];
+	let mut break_duration = chrono::Duration::zero();
+	for session in sessions.into_iter().filter(|session| is_summarized(session, options)) {
+		let mut session_seconds = session.duration_until(open_session_end).num_seconds();
+		if session_seconds < 0 {
+			anomaly_count += 1;
+			continue
+		}
+		if let Some(ref rounding) = options.rounding {
+			session_seconds = round_seconds(session_seconds, rounding);
+		}
+		break_duration = break_duration.add(session.break_duration_until(open_session_end));
+		total_seconds_in_time_range += session_seconds;
+		let project = session.project.clone().unwrap_or_else(|| String::from("(no project)"));
+		*project_totals.entry(project).or_insert(0) += session_seconds;
+		session_durations.push((session, chrono::Duration::seconds(session_seconds)));
+	}
+	if options.hours_only {
+		println!("{}", (total_seconds_in_time_range as f64 / 36.0).round() / 100.0);
+		return
+	}
+	let mut daily_durations = session::daily_spans(session_durations.iter().map(|&(ref session, duration)| (session, duration)));
+	if options.fill_days {
+		daily_durations = fill_missing_days(daily_durations, start_time, end_time);
+		daily_durations.retain(|daily_duration| !options.excluded_weekdays.contains(&daily_duration.date.weekday()));
+	}
+	if options.tsv {
+		if let Err(e) = write_tsv(&daily_durations, &mut io::stdout()) {
+			println!("Couldn't print summary: {}.\nExiting.", e);
+			process::exit(1)
+		}
+		return
 	}
-	let mut last_punch_out_timestamp: chrono::DateTime<UTC> = chrono::UTC::now();
 	
-	loop {
-		let read_attempt = populate_record_at_offset_from_end(&mut config_file, &mut record, record_offset);
-		if !read_attempt.is_ok() || record.timestamp < start_time {
-			if total_seconds_in_current_day != 0 {
-				daily_durations.push(DailyDuration {
-						date: current_date,
-						duration: chrono::Duration::seconds(total_seconds_in_current_day)
-				});
+	let formatted_total = format_duration_as(chrono::Duration::seconds(total_seconds_in_time_range), options.duration_style);
+	let table = !options.plain && !options.summary_only;
+	let formatted_durations: Vec<String> = daily_durations.iter().
+		map(|daily_duration| format_duration_as(daily_duration.duration, options.duration_style)).collect();
+	let total_label = match options.project {
+		Some(ref project) => format!("{} ({})", options.total_label, project),
+		None => options.total_label.clone()
+	};
+	// The total's label spans the date and day columns, widening the hours column if needed.
+	let label_width = format_table_row("", "", "", "", 0).len();
+	let hours_width = formatted_durations.iter().map(|duration| duration.len()).
+		chain(vec!["Hours".len(), (total_label.len() + 2 + formatted_total.len()).saturating_sub(label_width)]).max().unwrap_or(0);
+	let formatted_idle: Vec<String> = daily_durations.iter().
+		map(|daily_duration| format_duration_as(daily_duration.idle_until(open_session_end), options.duration_style)).collect();
+	let idle_width = formatted_idle.iter().map(|idle| idle.len()).chain(vec!["Idle".len()]).max().unwrap_or(0);
+	let idle_column = |idle: &str| if options.show_idle { format!("  {:>width$}", idle, width = idle_width) } else { String::new() };
+	if !options.summary_only {
+		if table {
+			println!("{}{}", format_table_row("Date", "Day", "In-Out", "Hours", hours_width), idle_column("Idle"));
+		}
+		for ((daily_duration, formatted_duration), idle) in daily_durations.iter().zip(&formatted_durations).zip(&formatted_idle) {
+			let line = if table {
+				let row = format_table_row(&daily_duration.date.format("%F").to_string(), &daily_duration.date.format("%a").to_string(),
+					&format_day_span(daily_duration), formatted_duration, hours_width) + &idle_column(idle);
+				if daily_duration.open { row + " (open)" } else { row }
+			}
+			else if options.show_idle {
+				format!("{}: {} (idle {})", daily_duration.date.format("%F"), formatted_duration, idle)
+			}
+			else {
+				format!("{}: {}", daily_duration.date.format("%F"), formatted_duration)
+			};
+			let seconds = daily_duration.duration.num_seconds();
+			if seconds > LONG_DAY_SECONDS {
+				println!("{}", paint(line, "32", options.color));
+			}
+			else if seconds < SHORT_DAY_SECONDS {
+				println!("{}", paint(line, "2", options.color));
+			}
+			else {
+				println!("{}", line);
 			}
-			break
 		}
-		if record.timestamp.date() != current_date && day_count != 0 {
-			daily_durations.push(DailyDuration {
-					date: current_date,
-					duration: chrono::Duration::seconds(total_seconds_in_current_day)
-			});
-			
-			total_seconds_in_current_day = 0;
+		if table {
+			println!("{}", "-".repeat(format_table_row("", "", "", "", hours_width).len() + idle_column("").len()));
 		}
-		
-		if record.action == Action::PunchOut {
-			last_punch_out_timestamp = record.timestamp;
-		} else {
-			total_seconds_in_current_day += last_punch_out_timestamp.sub(record.timestamp).num_seconds();
-			total_seconds_in_time_range += last_punch_out_timestamp.sub(record.timestamp).num_seconds();
+		else {
+			println!();
 		}
-		
-		record_offset += 1;
-		current_date = record.timestamp.date();
-		day_count += 1;
+	}
+	let total = if table {
+		paint(format!("{:<width$}{}", total_label, formatted_total, width = label_width + hours_width - formatted_total.len()), "1", options.color)
+	}
+	else {
+		paint(format!("{}: {}", total_label, formatted_total), "1", options.color)
+	};
+	match options.target_seconds {
+		Some(target_seconds) => println!("{} ({})", total,
+			format_target_progress(total_seconds_in_time_range, target_seconds, options.duration_style, options.color)),
+		None => println!("{}", total)
+	}
+	if let Some(ref rate) = options.rate {
+		println!("Earnings: {}", format_money(total_seconds_in_time_range as f64 / 3600.0 * rate.per_hour, &rate.currency));
+	}
+	print_break_duration(break_duration, options.duration_style);
+	let worked_days: Vec<&DailyDuration> = daily_durations.iter().filter(|daily_duration| daily_duration.duration.num_seconds() > 0).collect();
+	println!("Days worked: {}", worked_days.len());
+	if let Some(longest_day) = worked_days.iter().max_by_key(|daily_duration| daily_duration.duration) {
+		let worked_seconds: i64 = worked_days.iter().map(|daily_duration| daily_duration.duration.num_seconds()).sum();
+		println!("Average per day: {}", format_duration_as(chrono::Duration::seconds(worked_seconds / worked_days.len() as i64), options.duration_style));
+		println!("Longest day: {} ({})", longest_day.date.format("%F"), format_duration_as(longest_day.duration, options.duration_style));
 	}
 	
-	daily_durations.reverse();
-	
+	if options.by_project {
+		println!();
+		for (project, seconds) in &project_totals {
+			println!("{}: {}", project, format_duration_as(chrono::Duration::seconds(*seconds), options.duration_style));
+		}
+	}
+	if anomaly_count > 0 {
+		println!();
+		println!("Skipped {} record(s) that don't fit a session, run `punch check` and `punch doctor` for details", anomaly_count);
+	}
+}
+
+// Open sessions only count when asked for, and sessions on excluded weekdays never do.
+fn is_summarized(session: &Session, options: &SummaryOptions) -> bool {
+	(session.end.is_some() || options.include_open_session) &&
+		(options.project.is_none() || session.project == options.project) &&
+		!options.excluded_weekdays.contains(&session.date().weekday())
+}
+fn write_tsv(daily_durations: &[DailyDuration], out: &mut dyn Write) -> io::Result<()> {
+	for daily_duration in daily_durations {
+		writeln!(out, "{}\t{}", daily_duration.date.format("%F"), daily_duration.duration.num_seconds())?;
+	}
+	Ok(())
+}
+
+// Hours per day of completed sessions, as a GitHub-flavored Markdown table for reports.
+fn write_markdown_table(journal: &Journal, start_time: chrono::DateTime<UTC>, duration_style: DurationStyle, out: &mut dyn Write) -> io::Result<()> {
+	let sessions = journal.sessions_between(start_time, chrono::UTC::now()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+	let daily_durations = session::daily_durations(sessions.iter().filter_map(|session| session.end.map(|end| (session.date(), session.duration_until(end)))));
+	let total = daily_durations.iter().fold(chrono::Duration::zero(), |total, daily_duration| total.add(daily_duration.duration));
+	writeln!(out, "| Date | Weekday | Hours |")?;
+	writeln!(out, "| --- | --- | ---: |")?;
 	for daily_duration in &daily_durations {
-		println!("{}: {}", daily_duration.date, format_duration(daily_duration.duration));
+		writeln!(out, "| {} | {} | {} |", daily_duration.date.format("%F"), daily_duration.date.format("%a"),
+			format_duration_as(daily_duration.duration, duration_style))?;
 	}
-	println!("\nTotal: {}", format_duration(chrono::Duration::seconds(total_seconds_in_time_range)))
+	writeln!(out, "| **Total** | | **{}** |", format_duration_as(total, duration_style))
+}
+
+fn format_table_row(date: &str, weekday: &str, span: &str, hours: &str, hours_width: usize) -> String {
+	format!("{:<10}  {:<3}  {:<11}  {:>width$}", date, weekday, span, hours, width = hours_width)
 }
 
+// First punch-in to last punch-out in local time, e.g. `09:00-17:30`; a day still open has no end.
+fn format_day_span(daily_duration: &DailyDuration) -> String {
+	let format_time = |timestamp: chrono::DateTime<UTC>| timestamp.with_timezone(&Local).format("%H:%M").to_string();
+	match daily_duration.first_in {
+		Some(first_in) if daily_duration.open => format!("{}-", format_time(first_in)),
+		Some(first_in) => format!("{}-{}", format_time(first_in), daily_duration.last_out.map(format_time).unwrap_or_default()),
+		None => String::new()
+	}
+}
 
-fn print_current_state() {
-    let mut config_file = journal::get_conf_file(true, false).unwrap();
-    let mut record = empty_record();
+fn fill_missing_days(daily_durations: Vec<DailyDuration>, start_time: chrono::DateTime<UTC>, end_time: chrono::DateTime<UTC>) -> Vec<DailyDuration> {
+	let last_date = end_time.sub(chrono::Duration::seconds(1)).with_timezone(&Local).date();
+	let mut recorded_days = daily_durations.into_iter().peekable();
+	let mut filled_days = vec![];
+	
+	let mut date = start_time.with_timezone(&Local).date();
+	while date <= last_date {
+		match recorded_days.peek() {
+			Some(daily_duration) if daily_duration.date == date => filled_days.extend(recorded_days.next()),
+			_ => filled_days.push(DailyDuration {
+				date,
+				duration: chrono::Duration::zero(),
+				first_in: None,
+				last_out: None,
+				open: false
+			})
+		}
+		date = date.succ();
+	}
+	filled_days.extend(recorded_days);
+	filled_days
+}
 
-    match populate_record_at_offset_from_end(&mut config_file, &mut record, 0) {
-    	Ok(_) => {},
-    	Err(e) => {
-    		println!("Couldn't read entry: {}.\nExiting.", e);
+fn diagnose_log(journal: &Journal, fix: bool) {
+	// Held from the read to the rewrite, so that a punch made meanwhile isn't lost.
+	let _lock = lock_journal(journal);
+	let mut contents = String::new();
+	let read_result = journal.open_shared().
+		and_then(|mut f| f.read_to_string(&mut contents));
+	if let Err(e) = read_result {
+		println!("Couldn't read punch log: {}.\nExiting.", e);
+		process::exit(1)
+	}
+	
+	let mut records: Vec<Record> = vec![];
+	let mut problem_count = 0;
+	let mut unreadable_count = 0;
+	for (index, line) in contents.lines().enumerate() {
+		if !record::is_record_line(line) {
+			continue
+		}
+		let line_number = index + 1;
+		let record = match Record::from_log_line(line) {
+			Ok(record) => record,
+			Err(e) => {
+				println!("line {}: unreadable record: {}", line_number, e);
+				problem_count += 1;
+				unreadable_count += 1;
+				continue
+			}
+		};
+		
+		if let Some(previous) = records.last() {
+			if record.timestamp < previous.timestamp {
+				println!("line {}: {} is earlier than the preceding record", line_number, record.timestamp.with_timezone(&Local));
+				problem_count += 1;
+			}
+			if record.action == previous.action {
+				println!("line {}: {:?} follows another {:?}", line_number, record.action, previous.action);
+				problem_count += 1;
+			}
+		}
+		records.push(record);
+	}
+	if !contents.is_empty() && !contents.ends_with('\n') {
+		println!("line {}: last record is not terminated by a newline", contents.lines().count());
+		problem_count += 1;
+	}
+	
+	println!("{} problem(s) found", problem_count);
+	if !fix || problem_count == 0 {
+		return
+	}
+	if unreadable_count > 0 {
+		println!("Not rewriting the log while it contains unreadable records; fix them by hand first.");
+		process::exit(1)
+	}
+	
+	records.sort_by_key(|record| record.timestamp);
+	match journal.replace_records(&records) {
+		Ok(_) if journal.is_dry_run() => {},
+		Ok(_) => println!("Rewrote {} records in chronological order", records.len()),
+		Err(e) => {
+			println!("Couldn't rewrite punch log: {}.\nExiting.", e);
 			process::exit(1)
-    	}
-    }
-    
-    if record.action == Action::PunchIn {
-    	let current_timestamp = chrono::UTC::now();
-    	let time_punched_in = current_timestamp.sub(record.timestamp);
-    	println!("Punched in since {} ({})", record.timestamp, format_duration(time_punched_in))
-    } 
-    else {
-    	let mut previous_record = empty_record();
-    	match populate_record_at_offset_from_end(&mut config_file, &mut previous_record, 1) {
-	    	Ok(_) => {},
-	    	Err(e) => {
-	    		println!("Couldn't read entry: {}.\nExiting.", e);
+		}
+	}
+}
+
+// Unlike `doctor --fix`, which keeps every record and sorts them, repair drops whatever can't
+// be trusted: unreadable lines, a partially written last line, and records older than the one
+// before them.
+fn repair_log(journal: &Journal, fix: bool) {
+	let _lock = lock_journal(journal);
+	let mut contents = String::new();
+	let read_result = journal.open_shared().
+		and_then(|mut f| f.read_to_string(&mut contents));
+	if let Err(e) = read_result {
+		println!("Couldn't read punch log: {}.\nExiting.", e);
+		process::exit(1)
+	}
+	
+	let mut records: Vec<Record> = vec![];
+	let mut dropped_count = 0;
+	let line_count = contents.lines().count();
+	let unterminated = !contents.is_empty() && !contents.ends_with('\n');
+	let mut truncated = false;
+	for (index, line) in contents.lines().enumerate() {
+		if !record::is_record_line(line) {
+			continue
+		}
+		let line_number = index + 1;
+		let record = match Record::from_log_line(line) {
+			Ok(record) => record,
+			Err(_) if line_number == line_count && unterminated => {
+				println!("line {}: truncated record '{}'", line_number, line);
+				dropped_count += 1;
+				truncated = true;
+				continue
+			},
+			Err(e) => {
+				println!("line {}: unreadable record: {}", line_number, e);
+				dropped_count += 1;
+				continue
+			}
+		};
+		if let Some(previous) = records.last() {
+			if record.timestamp < previous.timestamp {
+				println!("line {}: {} is earlier than the preceding record", line_number, record.timestamp.with_timezone(&Local));
+				dropped_count += 1;
+				continue
+			}
+		}
+		records.push(record);
+	}
+	
+	// A last record that reads fine but lacks its newline is kept; the rewrite adds the newline.
+	let missing_newline = unterminated && !truncated;
+	if missing_newline {
+		println!("line {}: last line is not terminated by a newline", line_count);
+	}
+	println!("{} bad record(s) found", dropped_count);
+	if !fix || (dropped_count == 0 && !missing_newline) {
+		return
+	}
+	let backup_path = match journal.backup() {
+		Ok(path) => path,
+		Err(e) => {
+			println!("Couldn't back up punch log: {}.\nExiting.", e);
+			process::exit(1)
+		}
+	};
+	match journal.replace_records(&records) {
+		Ok(_) if journal.is_dry_run() => {},
+		Ok(_) if dropped_count == 0 => println!("Added the missing newline, the original log is in {}", backup_path.display()),
+		Ok(_) => println!("Dropped {} record(s), the original log is in {}", dropped_count, backup_path.display()),
+		Err(e) => {
+			println!("Couldn't rewrite punch log: {}.\nExiting.", e);
+			process::exit(1)
+		}
+	}
+}
+
+fn lock_journal(journal: &Journal) -> journal::JournalLock {
+	match journal.lock() {
+		Ok(lock) => lock,
+		Err(e) => {
+			println!("Couldn't lock punch log: {}.\nExiting.", e);
+			process::exit(1)
+		}
+	}
+}
+
+fn check_log_sequence(journal: &Journal) {
+	let problem_count = report_sequence_problems(&exit_if_log_is_unreadable(journal.records()));
+	if problem_count > 0 {
+		println!("{} problem(s) found", problem_count);
+		process::exit(1)
+	}
+}
+
+// Over the whole log, or only sessions that started since `since`.
+// Prints each record that can't follow the one before it and returns how many there were.
+fn report_sequence_problems(records: &[Record]) -> usize {
+	let mut problem_count = 0;
+	// The log is treated as starting punched out, so a leading punch-out is reported too.
+	let mut last_action = Action::PunchOut;
+	
+	for (index, record) in records.iter().enumerate() {
+		if journal::check_transition(last_action, record.action).is_err() {
+			println!("record {}: {:?} at {} can't follow {:?}", index, record.action,
+				record.timestamp.with_timezone(&Local), last_action);
+			problem_count += 1;
+		}
+		last_action = record.action;
+	}
+	problem_count
+}
+
+fn print_stats(journal: &Journal, since: Option<DateTime<UTC>>) {
+	let records = exit_if_log_is_unreadable(journal.all_records());
+	let (first_record, last_record) = match (records.first(), records.last()) {
+		(Some(first_record), Some(last_record)) => (first_record, last_record),
+		_ => {
+			return print_no_punches()
+		}
+	};
+	
+	let sessions = match since {
+		Some(start_time) => match journal.sessions_between(start_time, chrono::UTC::now()) {
+			Ok(sessions) => sessions,
+			Err(e) => {
+				println!("Couldn't read entry: {}.\nExiting.", e);
 				process::exit(1)
-	    	}
-	    }
-    	
-    	let delta = record.timestamp.sub(previous_record.timestamp);
-    	println!("Previously punched in between {} and {} ({})", 
-    		previous_record.timestamp, record.timestamp, format_duration(delta))
-    }
+			}
+		},
+		None => session::pair_sessions(&records)
+	};
+	let mut session_durations: Vec<chrono::Duration> = sessions.iter().
+		filter_map(|session| session.end.map(|end| session.duration_until(end))).collect();
+	let total = session_durations.iter().fold(chrono::Duration::zero(), |total, duration| total.add(*duration));
+	println!("Total: {}", format_duration(total));
+	println!("Sessions: {}", session_durations.len());
+	if !session_durations.is_empty() {
+		session_durations.sort();
+		let middle = session_durations.len() / 2;
+		let median = if session_durations.len().is_multiple_of(2) {
+			(session_durations[middle - 1] + session_durations[middle]) / 2
+		}
+		else {
+			session_durations[middle]
+		};
+		let worked_days = session::daily_durations(sessions.iter().
+			filter_map(|session| session.end.map(|end| (session.date(), session.duration_until(end))))).len();
+		println!("Average session: {}", format_duration(total / session_durations.len() as i32));
+		println!("Median session: {}", format_duration(median));
+		println!("Longest session: {}", format_duration(session_durations[session_durations.len() - 1]));
+		println!("Shortest session: {}", format_duration(session_durations[0]));
+		println!("Average per worked day: {}", format_duration(total / worked_days as i32));
+	}
+	// A session still open today counts towards the streak, as it will be completed today.
+	let today = chrono::Local::today();
+	let worked_dates: Vec<chrono::Date<Local>> = session::daily_durations(sessions.iter().
+		filter(|session| session.end.is_some() || session.date() == today).
+		map(|session| (session.date(), session.duration_until(chrono::UTC::now())))).into_iter().
+		filter(|daily_duration| daily_duration.duration > chrono::Duration::zero()).map(|daily_duration| daily_duration.date).collect();
+	if !worked_dates.is_empty() {
+		let (current_streak, longest_streak) = count_streaks(&worked_dates, today);
+		println!("Current streak: {} day(s)", current_streak);
+		println!("Longest streak: {} day(s)", longest_streak);
+	}
+	if since.is_none() {
+		println!("First punch: {}", export::format_iso_timestamp(first_record.timestamp));
+		println!("Last punch: {}", export::format_iso_timestamp(last_record.timestamp));
+	}
+	if let Some(open_session) = sessions.iter().find(|session| session.end.is_none()) {
+		println!("Open session: since {} ({})", export::format_iso_timestamp(open_session.start),
+			format_duration(open_session.duration_until(chrono::UTC::now())));
+	}
 }
 
-fn format_duration(duration: chrono::Duration) -> String {
-	format!("{:02}h{:02}m", duration.num_hours(), duration.num_minutes() % 60)
+// Runs of consecutive calendar days in `dates`, which are sorted. The current streak is still
+// going if it ended yesterday, as today may not have been worked yet.
+fn count_streaks(dates: &[chrono::Date<Local>], today: chrono::Date<Local>) -> (usize, usize) {
+	let mut streak = 0;
+	let mut longest_streak = 0;
+	for (index, date) in dates.iter().enumerate() {
+		if index > 0 && dates[index - 1].succ() == *date {
+			streak += 1;
+		}
+		else if index == 0 || dates[index - 1] != *date {
+			streak = 1;
+		}
+		longest_streak = cmp::max(longest_streak, streak);
+	}
+	let current_streak = match dates.last() {
+		Some(last_date) if *last_date >= today.pred() => streak,
+		_ => 0
+	};
+	(current_streak, longest_streak)
 }
 
-fn get_last_record_action() -> Action {
-	let mut config_file = journal::get_conf_file(true, false).unwrap();
-    let mut record = empty_record();
-    
-    if config_file.metadata().unwrap().len() == 0 {
-    	return Action::Unset
-    }
+fn print_records(journal: &Journal, tail: Option<usize>) {
+	for record in &read_last_records(journal, tail.unwrap_or(usize::MAX)) {
+		println!("{}", describe_record(record))
+	}
+}
 
-    match populate_record_at_offset_from_end(&mut config_file, &mut record, 0) {
-    	Ok(_) => {},
-    	Err(e) => {
-    		println!("Couldn't create punch log: {}.\nExiting.", e);
-			process::exit(1)
-    	}
-    }
+fn print_log(journal: &Journal, count: usize, notes_only: bool) {
+	let mut records: Vec<(usize, Record)> = vec![];
 	
-	record.action
+	for (offset, read_attempt) in exit_if_log_is_unreadable(journal.records_from_end()).enumerate() {
+		if records.len() == count {
+			break
+		}
+		match read_attempt {
+			Ok(ref record) if notes_only && record.note.is_none() => {},
+			Ok(record) => records.push((offset, record)),
+			Err(e) => {
+				println!("Couldn't read entry: {}.\nExiting.", e);
+				process::exit(1)
+			}
+		}
+	}
+	if records.is_empty() {
+		return print_no_punches()
+	}
+	
+	for &(offset, ref record) in records.iter().rev() {
+		println!("{:>4}  {}", offset, describe_record(record))
+	}
+}
+
+fn read_last_records(journal: &Journal, count: usize) -> Vec<Record> {
+	let mut records: Vec<Record> = vec![];
+	
+	for read_attempt in exit_if_log_is_unreadable(journal.records_from_end()).take(count) {
+		match read_attempt {
+			Ok(record) => records.push(record),
+			Err(e) => {
+				println!("Couldn't read entry: {}.\nExiting.", e);
+				process::exit(1)
+			}
+		}
+	}
+	records.reverse();
+	records
 }
 
-fn ensure_last_record_is_of_action(expected_action: Action) {
+fn describe_record(record: &Record) -> String {
+	let action = match record.action {
+		Action::PunchIn => "IN",
+		Action::PunchOut => "OUT",
+		Action::BreakStart => "BREAK",
+		Action::BreakEnd => "RESUME",
+		Action::Unset => "UNSET"
+	};
+	let mut line = format!("{} {}", record.timestamp.with_timezone(&Local).format("%F %H:%M"), action);
+	if let Some(ref project) = record.project {
+		line.push_str(&format!(" [{}]", project));
+	}
+	if let Some(ref note) = record.note {
+		line.push_str(&format!(" {}", note));
+	}
+	line
+}
 
-    let last_action = get_last_record_action();
-    
-    if last_action == Action::Unset {
-    	return
+// Redraws the time worked in place every second, and stops once the session is closed, for
+// example by `punch out` in another terminal.
+fn watch_open_session(journal: &Journal) {
+	let mut watched = false;
+	loop {
+		let open_session = match journal.open_session() {
+			Ok(open_session) => open_session,
+			Err(e) => {
+				println!("\nCouldn't read entry: {}.\nExiting.", e);
+				process::exit(1)
+			}
+		};
+		let session = match open_session {
+			Some(session) => session,
+			None => break
+		};
+		let state = if session.is_on_break() { "On a break" } else { "Punched in" };
+		print!("\r{} for {}  ", state, format_duration(session.duration_until(chrono::UTC::now())));
+		let _ = io::stdout().flush();
+		watched = true;
+		thread::sleep(time::Duration::from_millis(WATCH_INTERVAL_MILLIS));
+	}
+	if watched {
+		println!();
+	}
+	print_current_state(journal, &read_last_session_records(journal), None, DEFAULT_MAX_SESSION_HOURS, None, DurationStyle::Short)
+}
+
+fn print_current_state(journal: &Journal, session_records: &[Record], project: Option<&str>, max_session_hours: f64, daily_goal_hours: Option<f64>, duration_style: DurationStyle) {
+    let (first_record, last_record) = match (session_records.first(), session_records.last()) {
+    	(Some(first_record), Some(last_record)) => (first_record, last_record),
+    	_ => return print_no_punches()
+    };
+    let current_timestamp = chrono::UTC::now();
+    let session = session::pair_sessions(session_records).pop();
+    let state = LogState::of(Some(last_record));
+    if last_record.timestamp > current_timestamp {
+    	println!("Warning: the last record is at {}, later than now; check the system clock.", last_record.timestamp.with_timezone(&Local));
+    }
+    if let Some(project) = project {
+    	let is_punched_in_to_project = state == LogState::OpenSession &&
+    		session.as_ref().is_some_and(|session| session.project.as_deref() == Some(project));
+    	if !is_punched_in_to_project {
+    		println!("Not punched in to project {}", project);
+    		return
+    	}
     }
     
-    if last_action != expected_action {
-    	match expected_action {
-    		Action::PunchIn => {
-    			println!("Already punched out, punch in first!");
-				process::exit(0)    			
+    if state == LogState::OpenSession {
+    	let session_start = session.as_ref().map_or(first_record.timestamp, |session| session.start);
+    	let time_worked = session.as_ref().map_or(current_timestamp.sub(session_start), |session| session.duration_until(current_timestamp));
+    	println!("Punched in since {} ({})", session_start.with_timezone(&Local), format_duration_as(time_worked, duration_style));
+    	if let Some(goal_hours) = daily_goal_hours {
+    		let goal = chrono::Duration::seconds((goal_hours * 3600.0) as i64);
+    		let worked_today = today_total(journal);
+    		println!("{} {} / {}", format_progress_bar(worked_today, goal), format_duration_as(worked_today, duration_style),
+    			format_duration_as(goal, duration_style));
+    		let remaining = goal.sub(worked_today);
+    		if remaining > chrono::Duration::zero() {
+    			println!("Clock out at {} to hit {} today", current_timestamp.add(remaining).with_timezone(&Local).format("%H:%M"),
+    				format_duration_as(goal, duration_style));
     		}
-    		Action::PunchOut => {
-    			println!("Already punched in, punch out first!");
-    			process::exit(0)
-    		}
-    		Action::Unset => {
-    			// log file could be empty, this is ok.
+    		else {
+    			println!("Daily goal reached");
     		}
     	}
+    	if last_record.action == Action::BreakStart {
+    		println!("On a break since {}", last_record.timestamp.with_timezone(&Local))
+    	}
+    	if current_timestamp.sub(session_start).num_seconds() as f64 > max_session_hours * 3600.0 {
+    		println!("Warning: punched in for more than {} hours since {}, did you forget to punch out?",
+    			max_session_hours, session_start.with_timezone(&Local));
+    	}
+    	if let Some(ref session) = session {
+    		print_break_duration(session.break_duration_until(current_timestamp), duration_style)
+    	}
+    	if let Some(ref project) = first_record.project {
+    		println!("Project: {}", project)
+    	}
+    	if let Some(ref note) = first_record.note {
+    		println!("Note: {}", note)
+    	}
+    } 
+    else {
+    	match session {
+    		Some(ref session) if session.end.is_some() => {
+    			let end = last_record.timestamp;
+		    	println!("Previously punched in between {} and {} ({})", 
+		    		session.start.with_timezone(&Local), end.with_timezone(&Local), format_duration_as(session.duration_until(end), duration_style));
+		    	print_break_duration(session.break_duration_until(end), duration_style)
+    		},
+	    	_ => println!("Punched out at {} (no previous session to show)", last_record.timestamp.with_timezone(&Local))
+	    }
+    	if let Some(ref note) = last_record.note {
+    		println!("Note: {}", note)
+    	}
     }
 }
 
-fn empty_record() -> Record {
-	Record {
-    	action: Action::Unset,
-    	timestamp: chrono::UTC::now()
-    }
+fn print_today_total(journal: &Journal, duration_style: DurationStyle) {
+	println!("Today: {}", format_duration_as(today_total(journal), duration_style))
 }
 
-fn populate_record_at_offset_from_end(config_file: &mut File, record: &mut Record, offset_from_end: u64) -> Result<(), String> {
-	return seek_to_record_offset(config_file, offset_from_end).
-		and_then(|_| populate_record_at_current_offset(config_file, record))
+// Time worked since the start of the local day, including the open session.
+fn today_total(journal: &Journal) -> chrono::Duration {
+	let now = chrono::UTC::now();
+	let sessions = match journal.sessions_between(start_of_local_day(chrono::Local::today()), now) {
+		Ok(sessions) => sessions,
+		Err(e) => {
+			println!("Couldn't read entry: {}.\nExiting.", e);
+			process::exit(1)
+		}
+	};
+	sessions.iter().map(|session| session.duration_until(now)).
+		filter(|duration| *duration > chrono::Duration::zero()).
+		fold(chrono::Duration::zero(), |total, duration| total.add(duration))
 }
 
-fn populate_record_at_current_offset(f: &mut File, record: &mut Record) -> Result<(), String> {
-	let mut data = [0 as u8; RECORD_LENGTH];
-	let read = f.read(&mut data);
-	if read.unwrap() != RECORD_LENGTH {
-		panic!("Could not read complete record of {} bytes", RECORD_LENGTH)
+fn format_progress_bar(done: chrono::Duration, goal: chrono::Duration) -> String {
+	let filled = if goal > chrono::Duration::zero() {
+		cmp::min(PROGRESS_BAR_WIDTH, (done.num_seconds() * PROGRESS_BAR_WIDTH as i64 / goal.num_seconds()) as usize)
 	}
-	let (ts_data, rest) = data.split_at(19);
-	let timestamp = str::from_utf8(&ts_data).unwrap();
-	let parse_result = chrono::UTC.datetime_from_str(&timestamp, "%FT%T");
-	
-	let record_ts = parse_result.unwrap().with_timezone(&chrono::UTC);
-	record.timestamp = record_ts;
-	let action_string = str::from_utf8(&rest).unwrap();
-	if action_string == "_O\n" {
-		record.action = Action::PunchOut;
+	else {
+		PROGRESS_BAR_WIDTH
+	};
+	format!("[{}{}]", "#".repeat(filled), "-".repeat(PROGRESS_BAR_WIDTH - filled))
+}
+
+fn print_break_duration(break_duration: chrono::Duration, duration_style: DurationStyle) {
+	if break_duration > chrono::Duration::zero() {
+		println!("Breaks: {}", format_duration_as(break_duration, duration_style))
+	}
+}
+
+fn print_short_status(session_records: &[Record], project: Option<&str>) {
+	match session::pair_sessions(session_records).pop().filter(|session| session.end.is_none()) {
+		Some(ref session) if project.is_none() || session.project.as_deref() == project => {
+			let minutes = session.duration_until(chrono::UTC::now()).num_minutes();
+			println!("{} {}h{:02}m", if session.is_on_break() { "BREAK" } else { "IN" }, minutes / 60, minutes % 60)
+		},
+		_ => println!("OUT")
+	}
+}
+
+// Amounts are rounded to cents and grouped by thousands, e.g. $3,612.50 or 3,612.50 CHF.
+fn format_money(amount: f64, currency: &str) -> String {
+	let cents = (amount.abs() * 100.0).round() as i64;
+	let whole = (cents / 100).to_string();
+	let mut grouped = String::new();
+	for (i, digit) in whole.chars().enumerate() {
+		if i > 0 && (whole.len() - i).is_multiple_of(3) {
+			grouped.push(',');
+		}
+		grouped.push(digit);
+	}
+	let sign = if amount < 0.0 && cents > 0 { "-" } else { "" };
+	let number = format!("{}.{:02}", grouped, cents % 100);
+	match currency.to_uppercase().as_str() {
+		"USD" => format!("{}${}", sign, number),
+		"EUR" => format!("{}€{}", sign, number),
+		"GBP" => format!("{}£{}", sign, number),
+		other => format!("{}{} {}", sign, number, other)
+	}
+}
+
+fn round_seconds(seconds: i64, rounding: &Rounding) -> i64 {
+	let increment = rounding.increment_seconds;
+	let remainder = seconds % increment;
+	if remainder == 0 {
+		return seconds
+	}
+	match rounding.mode {
+		RoundingMode::Down => seconds - remainder,
+		RoundingMode::Up => seconds - remainder + increment,
+		RoundingMode::Nearest if remainder * 2 >= increment => seconds - remainder + increment,
+		RoundingMode::Nearest => seconds - remainder
+	}
+}
+
+fn format_target_progress(total_seconds: i64, target_seconds: i64, duration_style: DurationStyle, color: bool) -> String {
+	let difference_seconds = total_seconds - target_seconds;
+	let magnitude = format_duration_as(chrono::Duration::seconds(difference_seconds.abs()), duration_style);
+	let percentage = total_seconds * 100 / target_seconds;
+	if difference_seconds == 0 {
+		paint(String::from("on target"), "32", color)
+	}
+	else if difference_seconds > 0 {
+		paint(format!("over by {}, {}% of target", magnitude, percentage), "32", color)
 	}
-	else if action_string == "_I\n" {
-		record.action = Action::PunchIn;
-	} 
 	else {
-		return Err(format!("Could not determine action type from '{}'", action_string))
+		paint(format!("{} remaining, {}% of target", magnitude, percentage), "33", color)
+	}
+}
+
+fn should_color(when: Option<&str>) -> bool {
+	match when {
+		Some("always") => true,
+		Some("never") => false,
+		_ => env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && io::stdout().is_terminal()
 	}
-	Ok(())
 }
 
-fn seek_to_record_offset(f: &mut File, record_offset: u64) -> Result<(), String> {
-	let m = f.metadata().unwrap();
-	let file_len = m.len();
+fn paint(text: String, code: &str, color: bool) -> String {
+	if color {
+		format!("\x1b[{}m{}\x1b[0m", code, text)
+	}
+	else {
+		text
+	}
+}
+
+fn format_duration_as(duration: chrono::Duration, duration_style: DurationStyle) -> String {
+	match duration_style {
+		DurationStyle::Short => format_duration(duration),
+		DurationStyle::Long => format_duration_long(duration),
+		DurationStyle::Decimal => format!("{:.2}", duration.num_seconds() as f64 / 3600.0),
+		DurationStyle::WithDays => format_duration_with_days(duration)
+	}
+}
+
+// Negative durations, e.g. from out of order records, get a single leading `-`.
+fn duration_sign(duration: chrono::Duration) -> &'static str {
+	if duration < chrono::Duration::zero() { "-" } else { "" }
+}
+
+fn format_duration(duration: chrono::Duration) -> String {
+	let minutes = duration.num_minutes().abs();
+	format!("{}{:02}h{:02}m", duration_sign(duration), minutes / 60, minutes % 60)
+}
+
+fn format_duration_with_days(duration: chrono::Duration) -> String {
+	let minutes = duration.num_minutes().abs();
+	if minutes < 24 * 60 {
+		return format_duration(duration)
+	}
+	format!("{}{}d {:02}h{:02}m", duration_sign(duration), minutes / (24 * 60), minutes / 60 % 24, minutes % 60)
+}
+
+fn format_duration_long(duration: chrono::Duration) -> String {
+	let hours = duration.num_hours().abs();
+	let minutes = duration.num_minutes().abs() % 60;
+	let minutes_text = format!("{} minute{}", minutes, if minutes == 1 { "" } else { "s" });
+	if hours == 0 {
+		return format!("{}{}", duration_sign(duration), minutes_text)
+	}
+	format!("{}{} hour{} {}", duration_sign(duration), hours, if hours == 1 { "" } else { "s" }, minutes_text)
+}
+
+fn get_log_state(journal: &Journal) -> LogState {
+	match journal.state() {
+		Ok(state) => state,
+		Err(e) => {
+			println!("Couldn't read punch log: {}.\nExiting.", e);
+			process::exit(1)
+		}
+	}
+}
+
+fn print_no_punches() {
+	println!("No punches yet")
+}
+
+fn exit_if_log_is_unreadable<T>(open_result: io::Result<T>) -> T {
+	match open_result {
+		Ok(opened) => opened,
+		Err(e) => {
+			println!("Couldn't read punch log: {}.\nExiting.", e);
+			process::exit(1)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	use std::fs;
 	
-	if file_len < RECORD_LENGTH as u64 {
-		return Err(String::from("No data in log - punch in first!"))
+	use test_support::journal_with;
+	
+	fn summary_options() -> SummaryOptions {
+		SummaryOptions {
+			duration_style: DurationStyle::Short,
+			by_project: false,
+			include_open_session: false,
+			target_seconds: None,
+			rounding: None,
+			rate: None,
+			project: None,
+			excluded_weekdays: vec![],
+			summary_only: false,
+			plain: false,
+			tsv: false,
+			hours_only: false,
+			fill_days: false,
+			show_idle: false,
+			total_label: String::from("Total"),
+			color: false
+		}
+	}
+	
+	fn session_on(date: chrono::Date<Local>) -> Session {
+		let start = date.and_hms(9, 0, 0).with_timezone(&UTC);
+		Session {
+			start,
+			end: Some(start + chrono::Duration::hours(8)),
+			project: None,
+			breaks: vec![]
+		}
 	}
 	
-	let record_length_in_bytes = RECORD_LENGTH as u64;
-	let seek_offset = (file_len as i64 - ((record_offset + 1) * record_length_in_bytes) as i64) as u64;
-	let seek_result = f.seek(SeekFrom::Start(seek_offset));
-	if !seek_result.is_ok() {
-		return Err(format!("Failed to seek: {}", seek_result.err().unwrap()))
+	fn record_at(timestamp: &str, action: Action) -> Record {
+		Record {
+			timestamp: DateTime::parse_from_rfc3339(timestamp).unwrap().with_timezone(&UTC),
+			action,
+			project: None,
+			note: None
+		}
 	}
-	if seek_result.unwrap() != seek_offset {
-		return Err(format!("Could not seek to record offset {}", seek_offset))
+	
+	#[test]
+	fn shows_a_log_with_only_a_punch_out() {
+		let journal = journal_with(&["2024-03-04T17:00:00Z\tO"]);
+		let session_records = read_last_session_records(&journal);
+		assert!(last_completed_session(&session_records).is_none());
+		print_current_state(&journal, &session_records, None, DEFAULT_MAX_SESSION_HOURS, None, DurationStyle::Short);
+	}
+	
+	#[test]
+	fn rounds_each_session() {
+		let quarter_hour = |mode| Rounding {
+			increment_seconds: 15 * 60,
+			mode
+		};
+		assert_eq!(round_seconds(7 * 60, &quarter_hour(RoundingMode::Up)), 15 * 60);
+		assert_eq!(round_seconds(7 * 60, &quarter_hour(RoundingMode::Nearest)), 0);
+		assert_eq!(round_seconds(8 * 60, &quarter_hour(RoundingMode::Nearest)), 15 * 60);
+		assert_eq!(round_seconds(14 * 60, &quarter_hour(RoundingMode::Down)), 0);
+		assert_eq!(round_seconds(30 * 60, &quarter_hour(RoundingMode::Up)), 30 * 60);
+	}
+	
+	#[test]
+	fn starts_the_week_on_the_configured_day() {
+		let wednesday = Local.ymd(2024, 3, 6);
+		assert_eq!(start_of_week(wednesday, chrono::Weekday::Mon), Local.ymd(2024, 3, 4));
+		assert_eq!(start_of_week(wednesday, chrono::Weekday::Sun), Local.ymd(2024, 3, 3));
+		assert_eq!(start_of_week(wednesday, chrono::Weekday::Wed), wednesday);
+	}
+	
+	#[test]
+	fn repairs_a_truncated_tail_and_a_bad_token() {
+		let journal = journal_with(&["2024-03-04T09:00:00Z\tI", "2024-03-04T12:00:00Z\tX", "2024-03-04T17:00:00Z\tO"]);
+		fs::OpenOptions::new().append(true).open(journal.path()).unwrap().write_all(b"2024-03-05T09:0").unwrap();
+		repair_log(&journal, true);
+		let actions: Vec<Action> = journal.records().unwrap().iter().map(|record| record.action).collect();
+		assert_eq!(actions, vec![Action::PunchIn, Action::PunchOut]);
+		assert!(fs::read_to_string(journal.path().with_extension("log.bak")).unwrap().ends_with("2024-03-05T09:0"));
+	}
+	
+	#[test]
+	fn reports_broken_sequences() {
+		let records = vec![
+			record_at("2024-03-04T08:00:00Z", Action::PunchOut),
+			record_at("2024-03-04T09:00:00Z", Action::PunchIn),
+			record_at("2024-03-04T10:00:00Z", Action::PunchIn),
+			record_at("2024-03-04T11:00:00Z", Action::BreakEnd),
+			record_at("2024-03-04T17:00:00Z", Action::PunchOut)
+		];
+		assert_eq!(report_sequence_problems(&records), 3);
+		assert_eq!(report_sequence_problems(&records[1..2]), 0);
+	}
+	
+	#[test]
+	fn leaves_out_excluded_weekdays() {
+		let options = SummaryOptions {
+			excluded_weekdays: vec![chrono::Weekday::Sat, chrono::Weekday::Sun],
+			..summary_options()
+		};
+		let included: Vec<u32> = (4..11).map(|day| session_on(Local.ymd(2024, 3, day))).
+			filter(|session| is_summarized(session, &options)).map(|session| session.date().day()).collect();
+		assert_eq!(included, vec![4, 5, 6, 7, 8]);
+		assert!(is_summarized(&session_on(Local.ymd(2024, 3, 9)), &summary_options()));
+	}
+	
+	#[test]
+	fn starts_a_days_range_that_many_days_back() {
+		assert_eq!(start_of_days_ago(Local.ymd(2024, 3, 6), 7), Local.ymd(2024, 2, 28));
+		assert_eq!(start_of_days_ago(Local.ymd(2024, 3, 6), 1), Local.ymd(2024, 3, 5));
+	}
+	
+	#[test]
+	fn prints_one_tsv_line_per_day() {
+		let sessions: Vec<Session> = vec![4, 5, 7].into_iter().map(|day| session_on(Local.ymd(2024, 3, day))).collect();
+		let daily_durations = session::daily_durations(sessions.iter().map(|session| (session.date(), chrono::Duration::hours(8))));
+		let mut out = vec![];
+		write_tsv(&daily_durations, &mut out).unwrap();
+		let text = String::from_utf8(out).unwrap();
+		assert_eq!(text.lines().collect::<Vec<&str>>(), vec!["2024-03-04\t28800", "2024-03-05\t28800", "2024-03-07\t28800"]);
+	}
+	
+	#[test]
+	fn formats_negative_and_long_durations() {
+		let negative = chrono::Duration::minutes(-90);
+		assert_eq!(format_duration(negative), "-01h30m");
+		assert_eq!(format_duration_long(negative), "-1 hour 30 minutes");
+		let long = chrono::Duration::hours(30);
+		assert_eq!(format_duration(long), "30h00m");
+		assert_eq!(format_duration_with_days(long), "1d 06h00m");
+		assert_eq!(format_duration_with_days(chrono::Duration::minutes(-(28 * 60 + 30))), "-1d 04h30m");
+	}
+	
+	#[test]
+	fn counts_streaks_across_gaps() {
+		let dates: Vec<chrono::Date<Local>> = vec![1, 2, 3, 5, 6, 7, 8, 10, 11].into_iter().map(|day| Local.ymd(2024, 3, day)).collect();
+		assert_eq!(count_streaks(&dates, Local.ymd(2024, 3, 11)), (2, 4));
+		assert_eq!(count_streaks(&dates, Local.ymd(2024, 3, 12)), (2, 4));
+		assert_eq!(count_streaks(&dates, Local.ymd(2024, 3, 13)), (0, 4));
+		assert_eq!(count_streaks(&[], Local.ymd(2024, 3, 13)), (0, 0));
+	}
+	
+	#[test]
+	fn no_color_leaves_out_escape_sequences() {
+		env::set_var("NO_COLOR", "1");
+		let color = should_color(None);
+		assert!(!color);
+		assert!(should_color(Some("always")));
+		let progress = format_target_progress(3600, 7200, DurationStyle::Short, color);
+		assert!(!progress.contains('\x1b'), "{:?}", progress);
+		assert!(!paint(String::from("Total"), "1", color).contains('\x1b'));
+	}
+	
+	#[test]
+	fn repairs_a_last_record_without_a_newline() {
+		let journal = journal_with(&["2024-03-04T09:00:00Z\tI"]);
+		fs::OpenOptions::new().append(true).open(journal.path()).unwrap().write_all(b"2024-03-04T17:00:00Z\tO").unwrap();
+		assert!(journal.records().is_err());
+		repair_log(&journal, true);
+		let actions: Vec<Action> = journal.records().unwrap().iter().map(|record| record.action).collect();
+		assert_eq!(actions, vec![Action::PunchIn, Action::PunchOut]);
+		assert!(fs::read_to_string(journal.path()).unwrap().ends_with("\tO\n"));
 	}
-	Ok(())
 }
-
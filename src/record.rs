@@ -0,0 +1,275 @@
+use std::fmt;
+
+use chrono;
+use chrono::DateTime;
+use chrono::TimeZone;
+use chrono::UTC;
+
+const TIMESTAMP_LENGTH: usize = 19;
+pub const LOG_HEADER: &str = "# punch v2";
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Action {
+	#[cfg_attr(feature = "serde", serde(rename = "in"))]
+	PunchIn,
+	#[cfg_attr(feature = "serde", serde(rename = "out"))]
+	PunchOut,
+	#[cfg_attr(feature = "serde", serde(rename = "break"))]
+	BreakStart,
+	#[cfg_attr(feature = "serde", serde(rename = "resume"))]
+	BreakEnd,
+	#[cfg_attr(feature = "serde", serde(rename = "unset"))]
+	Unset
+}
+
+impl Action {
+	// The lowercase name used in exports, matching the serde representation.
+	pub fn name(self) -> &'static str {
+		match self {
+			Action::PunchIn => "in",
+			Action::PunchOut => "out",
+			Action::BreakStart => "break",
+			Action::BreakEnd => "resume",
+			Action::Unset => "unset"
+		}
+	}
+	
+	// Breaks happen within a session, so the session stays open until the punch-out.
+	pub fn leaves_session_open(self) -> bool {
+		match self {
+			Action::PunchIn | Action::BreakStart | Action::BreakEnd => true,
+			Action::PunchOut | Action::Unset => false
+		}
+	}
+}
+
+impl fmt::Display for Action {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.name())
+	}
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Record {
+	#[cfg_attr(feature = "serde", serde(with = "rfc3339"))]
+	pub timestamp: DateTime<UTC>,
+	pub action: Action,
+	#[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+	pub project: Option<String>,
+	#[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+	pub note: Option<String>
+}
+
+impl Record {
+	pub fn to_log_line(&self) -> String {
+		let action_token = match self.action {
+			Action::PunchIn => "I",
+			Action::PunchOut => "O",
+			Action::BreakStart => "B",
+			Action::BreakEnd => "R",
+			Action::Unset => "U"
+		};
+	
+		let mut line = format!("{}\t{}", self.timestamp.format("%FT%TZ"), action_token);
+		if let Some(ref project) = self.project {
+			line.push_str(&format!("\tproject={}", escape_field_value(project)));
+		}
+		if let Some(ref note) = self.note {
+			line.push_str(&format!("\tnote={}", escape_field_value(note)));
+		}
+		line.push('\n');
+		line
+	}
+	
+	pub fn from_log_line(line: &str) -> Result<Record, String> {
+		let mut record = empty_record();
+		populate_record_from_line(line, &mut record).map(|_| record)
+	}
+}
+
+impl fmt::Display for Record {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{} {}", self.timestamp.format("%FT%TZ"), self.action)
+	}
+}
+
+// The log starts with a `LOG_HEADER` line naming its format version; blank lines (including
+// ones with only whitespace) and lines starting with `#` never hold records.
+pub fn is_record_line(line: &str) -> bool {
+	let line = line.trim_start();
+	!line.is_empty() && !line.starts_with('#')
+}
+
+fn empty_record() -> Record {
+	Record {
+    	action: Action::Unset,
+    	timestamp: chrono::UTC::now(),
+    	project: None,
+    	note: None
+    }
+}
+
+fn escape_field_value(value: &str) -> String {
+	value.replace('%', "%25").replace('\t', "%09").replace('\n', "%0A")
+}
+
+fn unescape_field_value(value: &str) -> String {
+	value.replace("%0A", "\n").replace("%09", "\t").replace("%5F", "_").replace("%25", "%")
+}
+
+// v2 records are `<RFC 3339 timestamp>\t<action>` followed by optional `\t<key>=<value>`
+// fields. v1 records (written before the header was introduced) never contain a tab, and
+// are either fixed-width `<timestamp>_<action>` lines or carry `_<key>=<value>` fields.
+fn populate_record_from_line(line: &str, record: &mut Record) -> Result<(), String> {
+	if line.contains('\t') {
+		populate_record_from_v2_line(line, record)
+	}
+	else {
+		populate_record_from_v1_line(line, record)
+	}
+}
+
+fn populate_record_from_v2_line(line: &str, record: &mut Record) -> Result<(), String> {
+	let mut fields = line.split('\t');
+	let timestamp = fields.next().unwrap_or("");
+	record.timestamp = match DateTime::parse_from_rfc3339(timestamp) {
+		Ok(ts) => ts.with_timezone(&chrono::UTC),
+		Err(e) => return Err(format!("Could not parse timestamp '{}': {}", timestamp, e))
+	};
+	let action_token = fields.next().unwrap_or("");
+	record.action = match action_from_token(action_token.as_bytes()) {
+		Some(action) => action,
+		None => return Err(format!("Could not determine action type from '{}'", action_token))
+	};
+	populate_record_fields(fields, record);
+	Ok(())
+}
+
+fn populate_record_from_v1_line(line: &str, record: &mut Record) -> Result<(), String> {
+	if line.len() < TIMESTAMP_LENGTH || !line.is_char_boundary(TIMESTAMP_LENGTH) {
+		return Err(format!("Could not read record from '{}'", line))
+	}
+	let (timestamp, rest) = line.split_at(TIMESTAMP_LENGTH);
+	let parse_result = chrono::UTC.datetime_from_str(timestamp, "%FT%T");
+
+	record.timestamp = match parse_result {
+		Ok(ts) => ts.with_timezone(&chrono::UTC),
+		Err(e) => return Err(format!("Could not parse timestamp '{}': {}", timestamp, e))
+	};
+	let mut fields = rest.split('_').skip(1);
+	record.action = match fields.next() {
+		Some("O") => Action::PunchOut,
+		Some("I") => Action::PunchIn,
+		_ => return Err(format!("Could not determine action type from '{}'", rest))
+	};
+	populate_record_fields(fields, record);
+	Ok(())
+}
+
+// Matched as bytes, so that reading the action costs no more than the comparison; only an
+// unrecognized token gets formatted into an error.
+fn action_from_token(token: &[u8]) -> Option<Action> {
+	match token {
+		b"O" => Some(Action::PunchOut),
+		b"I" => Some(Action::PunchIn),
+		b"B" => Some(Action::BreakStart),
+		b"R" => Some(Action::BreakEnd),
+		_ => None
+	}
+}
+
+// Unknown keys are skipped so that older builds can still read logs written by newer ones.
+fn populate_record_fields<'a, I: Iterator<Item = &'a str>>(fields: I, record: &mut Record) {
+	record.project = None;
+	record.note = None;
+	for field in fields {
+		let mut key_value = field.splitn(2, '=');
+		match (key_value.next(), key_value.next()) {
+			(Some("project"), Some(value)) => record.project = Some(unescape_field_value(value)),
+			(Some("note"), Some(value)) => record.note = Some(unescape_field_value(value)),
+			_ => {}
+		}
+	}
+}
+
+#[cfg(feature = "serde")]
+mod rfc3339 {
+	use chrono::DateTime;
+	use chrono::UTC;
+	use serde::Deserialize;
+	use serde::Deserializer;
+	use serde::Serializer;
+	use serde::de::Error;
+
+	pub fn serialize<S: Serializer>(timestamp: &DateTime<UTC>, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_str(&timestamp.format("%FT%TZ").to_string())
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<UTC>, D::Error> {
+		let text = String::deserialize(deserializer)?;
+		DateTime::parse_from_rfc3339(&text).map(|ts| ts.with_timezone(&UTC)).map_err(D::Error::custom)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn record(action: Action, project: Option<&str>, note: Option<&str>) -> Record {
+		Record {
+			timestamp: UTC.ymd(2024, 3, 4).and_hms(9, 30, 15),
+			action,
+			project: project.map(String::from),
+			note: note.map(String::from)
+		}
+	}
+	
+	#[test]
+	fn reads_v1_lines() {
+		assert_eq!(Record::from_log_line("2024-03-04T09:30:15_I"), Ok(record(Action::PunchIn, None, None)));
+		assert_eq!(Record::from_log_line("2024-03-04T09:30:15_O_note=done"), Ok(record(Action::PunchOut, None, Some("done"))));
+		assert_eq!(Record::from_log_line("2024-03-04T09:30:15_I_project=acme"), Ok(record(Action::PunchIn, Some("acme"), None)));
+	}
+	
+	#[cfg(feature = "serde")]
+	#[test]
+	fn serde_round_trip() {
+		use toml;
+		
+		let record = record(Action::PunchIn, Some("acme"), None);
+		let text = toml::to_string(&record).unwrap();
+		assert!(text.contains("timestamp = \"2024-03-04T09:30:15Z\""), "{}", text);
+		assert!(text.contains("action = \"in\""), "{}", text);
+		assert!(!text.contains("note"), "{}", text);
+		assert_eq!(toml::from_str::<Record>(&text).unwrap(), record);
+	}
+	
+	#[test]
+	fn log_lines_round_trip() {
+		let records = vec![
+			record(Action::PunchIn, None, None),
+			record(Action::PunchOut, None, Some("done for today")),
+			record(Action::BreakStart, None, None),
+			record(Action::BreakEnd, None, None),
+			record(Action::PunchIn, Some("acme\tweb"), Some("50% done\nsee %09 and %0A_literally"))
+		];
+		for record in records {
+			let line = record.to_log_line();
+			assert!(line.ends_with('\n') && line.matches('\n').count() == 1, "{:?}", line);
+			assert_eq!(Record::from_log_line(line.trim_end_matches('\n')), Ok(record));
+		}
+	}
+	
+	#[test]
+	fn rejects_unknown_action_tokens() {
+		assert!(Record::from_log_line("2024-03-04T09:30:15Z\tX").is_err());
+		assert!(Record::from_log_line("2024-03-04T09:30:15Z\tII").is_err());
+		assert!(Record::from_log_line("2024-03-04T09:30:15_X").is_err());
+	}
+}
@@ -0,0 +1,243 @@
+use std::cmp;
+use std::ops::Add;
+use std::ops::Sub;
+
+use chrono;
+use chrono::DateTime;
+use chrono::Local;
+use chrono::UTC;
+
+use record::Action;
+use record::Record;
+
+#[derive(Debug)]
+pub struct Session {
+	pub start: DateTime<UTC>,
+	pub end: Option<DateTime<UTC>>,
+	pub project: Option<String>,
+	pub breaks: Vec<Break>
+}
+
+#[derive(Debug)]
+pub struct Break {
+	pub start: DateTime<UTC>,
+	pub end: Option<DateTime<UTC>>
+}
+
+#[derive(Debug)]
+pub struct DailyDuration {
+	pub date: chrono::Date<Local>,
+	pub duration: chrono::Duration,
+	pub first_in: Option<DateTime<UTC>>,
+	pub last_out: Option<DateTime<UTC>>,
+	pub open: bool
+}
+
+impl Session {
+	pub fn date(&self) -> chrono::Date<Local> {
+		self.start.with_timezone(&Local).date()
+	}
+
+	// Time worked, leaving out breaks. An open session counts up to `until`, and never below
+	// zero when it started later than that, e.g. after the clock was set back.
+	pub fn duration_until(&self, until: DateTime<UTC>) -> chrono::Duration {
+		let duration = self.end.unwrap_or(until).sub(self.start).sub(self.break_duration_until(until));
+		match self.end {
+			Some(_) => duration,
+			None => cmp::max(duration, chrono::Duration::zero())
+		}
+	}
+	
+	pub fn break_duration_until(&self, until: DateTime<UTC>) -> chrono::Duration {
+		let session_end = self.end.unwrap_or(until);
+		self.breaks.iter().filter(|on_break| on_break.start < session_end).
+			fold(chrono::Duration::zero(), |total, on_break| {
+				total.add(cmp::min(on_break.end.unwrap_or(session_end), session_end).sub(on_break.start))
+			})
+	}
+	
+	pub fn is_on_break(&self) -> bool {
+		self.breaks.last().is_some_and(|on_break| on_break.end.is_none())
+	}
+}
+
+impl DailyDuration {
+	// Time between the first punch-in and the last punch-out that wasn't worked, i.e. breaks and
+	// gaps between sessions. A day still open runs up to `until`.
+	pub fn idle_until(&self, until: DateTime<UTC>) -> chrono::Duration {
+		let last_out = if self.open { Some(until) } else { self.last_out };
+		match (self.first_in, last_out) {
+			(Some(first_in), Some(last_out)) => cmp::max(last_out.sub(first_in).sub(self.duration), chrono::Duration::zero()),
+			_ => chrono::Duration::zero()
+		}
+	}
+}
+
+pub fn pair_sessions(records: &[Record]) -> Vec<Session> {
+	pair_sessions_counting_anomalies(records).0
+}
+
+// Records that don't fit a session are skipped and counted as anomalies: a punch-in while a
+// session is already open (the older one is dropped), a punch-out, break or resume outside a
+// session, and anything timestamped before the punch-in it follows.
+pub fn pair_sessions_counting_anomalies(records: &[Record]) -> (Vec<Session>, usize) {
+	let mut sessions = vec![];
+	let mut anomaly_count = 0;
+	let mut open_session: Option<Session> = None;
+
+	for record in records {
+		if let Some(ref session) = open_session {
+			if record.timestamp < session.start {
+				anomaly_count += 1;
+				continue
+			}
+		}
+		match record.action {
+			Action::PunchIn => {
+				if open_session.is_some() {
+					anomaly_count += 1;
+				}
+				open_session = Some(Session {
+					start: record.timestamp,
+					end: None,
+					project: record.project.clone(),
+					breaks: vec![]
+				})
+			},
+			Action::BreakStart => {
+				match open_session {
+					Some(ref mut session) if !session.is_on_break() => session.breaks.push(Break {
+						start: record.timestamp,
+						end: None
+					}),
+					_ => anomaly_count += 1
+				}
+			},
+			Action::BreakEnd => {
+				match open_session {
+					Some(ref mut session) if session.is_on_break() => end_open_break(session, record.timestamp),
+					_ => anomaly_count += 1
+				}
+			},
+			Action::PunchOut => {
+				match open_session.take() {
+					Some(mut session) => {
+						end_open_break(&mut session, record.timestamp);
+						session.end = Some(record.timestamp);
+						sessions.push(session);
+					},
+					None => anomaly_count += 1
+				}
+			},
+			Action::Unset => {}
+		}
+	}
+	if let Some(session) = open_session {
+		sessions.push(session);
+	}
+	(sessions, anomaly_count)
+}
+
+// Punching out while on a break ends the break too.
+fn end_open_break(session: &mut Session, timestamp: DateTime<UTC>) {
+	if let Some(on_break) = session.breaks.last_mut() {
+		if on_break.end.is_none() {
+			on_break.end = Some(timestamp);
+		}
+	}
+}
+
+// Sums durations per local day. Input is expected in chronological order, as read from the log.
+pub fn daily_durations<I: IntoIterator<Item = (chrono::Date<Local>, chrono::Duration)>>(durations: I) -> Vec<DailyDuration> {
+	let mut daily_durations: Vec<DailyDuration> = vec![];
+
+	for (date, duration) in durations {
+		match daily_durations.last_mut() {
+			Some(ref mut daily_duration) if daily_duration.date == date => {
+				daily_duration.duration = daily_duration.duration.add(duration);
+			},
+			_ => daily_durations.push(DailyDuration {
+				date,
+				duration,
+				first_in: None,
+				last_out: None,
+				open: false
+			})
+		}
+	}
+	daily_durations
+}
+
+// Like `daily_durations`, but also keeps when each day's first session started and its last one
+// ended. A day with a session still open has no last punch-out.
+pub fn daily_spans<'a, I: IntoIterator<Item = (&'a Session, chrono::Duration)>>(durations: I) -> Vec<DailyDuration> {
+	let mut daily_durations: Vec<DailyDuration> = vec![];
+
+	for (session, duration) in durations {
+		let date = session.date();
+		match daily_durations.last_mut() {
+			Some(ref mut daily_duration) if daily_duration.date == date => {
+				daily_duration.duration = daily_duration.duration.add(duration);
+				daily_duration.last_out = cmp::max(daily_duration.last_out, session.end);
+				daily_duration.open |= session.end.is_none();
+			},
+			_ => daily_durations.push(DailyDuration {
+				date,
+				duration,
+				first_in: Some(session.start),
+				last_out: session.end,
+				open: session.end.is_none()
+			})
+		}
+	}
+	daily_durations
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	use chrono::TimeZone;
+	
+	fn record_at(hour: u32, minute: u32, action: Action) -> Record {
+		Record {
+			timestamp: UTC.ymd(2024, 3, 4).and_hms(hour, minute, 0),
+			action,
+			project: None,
+			note: None
+		}
+	}
+	
+	#[test]
+	fn leaves_breaks_out_of_durations() {
+		let sessions = pair_sessions(&[record_at(9, 0, Action::PunchIn), record_at(12, 0, Action::BreakStart),
+			record_at(12, 45, Action::BreakEnd), record_at(17, 0, Action::PunchOut)]);
+		assert_eq!(sessions.len(), 1);
+		let end = sessions[0].end.unwrap();
+		assert_eq!(sessions[0].duration_until(end).num_minutes(), 8 * 60 - 45);
+		assert_eq!(sessions[0].break_duration_until(end).num_minutes(), 45);
+	}
+	
+	#[test]
+	fn counts_records_outside_a_session_as_anomalies() {
+		let (sessions, anomaly_count) = pair_sessions_counting_anomalies(&[record_at(8, 0, Action::PunchOut),
+			record_at(9, 0, Action::PunchIn), record_at(9, 30, Action::BreakEnd), record_at(17, 0, Action::PunchOut)]);
+		assert_eq!(sessions.len(), 1);
+		assert_eq!(anomaly_count, 2);
+	}
+	
+	#[test]
+	fn drops_a_punch_in_without_a_punch_out_mid_history() {
+		let (sessions, anomaly_count) = pair_sessions_counting_anomalies(&[record_at(8, 0, Action::PunchIn),
+			record_at(9, 0, Action::PunchIn), record_at(17, 0, Action::PunchOut)]);
+		assert_eq!(sessions.len(), 1);
+		assert_eq!(sessions[0].start, UTC.ymd(2024, 3, 4).and_hms(9, 0, 0));
+		assert_eq!(anomaly_count, 1);
+	}
+	
+	#[test]
+	fn open_session_starting_later_counts_as_zero() {
+		let sessions = pair_sessions(&[record_at(17, 0, Action::PunchIn)]);
+		assert_eq!(sessions[0].duration_until(UTC.ymd(2024, 3, 4).and_hms(9, 0, 0)), chrono::Duration::zero());
+	}
+}
@@ -0,0 +1,27 @@
+// Fixtures for the test modules. The binary's tests include this file too, so it only names
+// items that both crates have at their root.
+use std::env;
+use std::fs;
+use std::process;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use Journal;
+use record::LOG_HEADER;
+
+static NEXT_LOG_ID: AtomicUsize = AtomicUsize::new(0);
+
+// A journal over a fresh log under the temp dir, holding `lines` after the header.
+pub fn journal_with(lines: &[&str]) -> Journal {
+	let dir = env::temp_dir().join(format!("punch-test-{}-{}", process::id(), NEXT_LOG_ID.fetch_add(1, Ordering::SeqCst)));
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+	let journal = Journal::new(dir.join("punch.log"));
+	let mut contents = format!("{}\n", LOG_HEADER);
+	for line in lines {
+		contents.push_str(line);
+		contents.push('\n');
+	}
+	fs::write(journal.path(), contents).unwrap();
+	journal
+}